@@ -1,21 +1,31 @@
+use crate::statement::{DefaultRenderer, StatementRenderer, STATEMENT_PREAMBLE};
 use crate::RESOURCE_PREFIX;
 use cid::Cid;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
 use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, DeserializeAs, SerializeAs};
+use sha2::{Digest, Sha256};
 
 use iri_string::types::UriString;
 use siwe::Message;
+use time::OffsetDateTime;
 
 use ucan_capabilities_object::{
-    Ability, AbilityNameRef, AbilityNamespaceRef, Capabilities, CapsInner, ConvertError,
-    ConvertResult, NotaBeneCollection,
+    Ability, AbilityNameRef, AbilityNamespace, AbilityNamespaceRef, Capabilities, CapsInner,
+    ConvertError, ConvertResult, NotaBeneCollection,
 };
 
 /// Representation of a set of delegated Capabilities.
+///
+/// The serialized form is the ReCap object itself and its field names are stable:
+/// `att` maps each target URI to its abilities and their note-benes, and `prf` lists
+/// the supporting proof CIDs in base58btc. Unknown fields are rejected when deserializing.
+///
+/// Targets and abilities are kept sorted, so equality and hashing do not depend on the order they were added in.
 #[serde_as]
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(deny_unknown_fields)]
 pub struct Capability<NB> {
     /// The actions that are allowed for the given target within this namespace.
     #[serde(rename = "att")]
@@ -36,7 +46,13 @@ impl<NB> Capability<NB> {
         }
     }
 
-    /// Check if a particular action is allowed for the specified target, or is allowed globally.
+    /// Check if a particular action is allowed for the specified target.
+    ///
+    /// ReCaps have no default actions, so only abilities granted for exactly this target match. Use
+    /// [`Capability::can_do_wildcard`] to also match wildcard targets.
+    ///
+    /// Abilities are compared exactly as encoded, so ability namespaces and names are case-sensitive:
+    /// `Credential/present` does not match `credential/present`.
     pub fn can<T, A>(
         &self,
         target: T,
@@ -46,14 +62,87 @@ impl<NB> Capability<NB> {
         T: TryInto<UriString>,
         A: TryInto<Ability>,
     {
-        self.attenuations.can(target, action)
+        Ok(self.can_do(
+            &target.try_into().map_err(ConvertError::A)?,
+            &action.try_into().map_err(ConvertError::B)?,
+        ))
     }
 
-    /// Check if a particular action is allowed for the specified target, or is allowed globally, without type conversion.
+    /// Check if a particular action is allowed for the specified target, without type conversion.
+    ///
+    /// Only abilities granted for exactly this target match.
     pub fn can_do(&self, target: &UriString, action: &Ability) -> Option<&NotaBeneCollection<NB>> {
         self.attenuations.can_do(target, action)
     }
 
+    /// Check if a particular action is allowed for the specified target, including through wildcard targets.
+    ///
+    /// A granted target ending in `/*` covers every target under the part before the `*`. Targets with `.`
+    /// or `..` path segments, including percent-encoded ones, are never covered by a wildcard. Other targets
+    /// containing `*`, such as `credential:*`, are matched literally. An exact target match is preferred,
+    /// otherwise the longest covering wildcard target is used.
+    pub fn can_do_wildcard(
+        &self,
+        target: &UriString,
+        action: &Ability,
+    ) -> Option<&NotaBeneCollection<NB>> {
+        self.attenuations.can_do(target, action).or_else(|| {
+            self.attenuations
+                .abilities()
+                .iter()
+                .filter(|(t, _)| wildcard_covers(t.as_str(), target.as_str()))
+                .filter_map(|(t, abilities)| Some((t.as_str().len(), abilities.get(action)?)))
+                .max_by_key(|(len, _)| *len)
+                .map(|(_, nbs)| nbs)
+        })
+    }
+
+    /// List every ability which applies to the specified target, along with the grant it comes from.
+    ///
+    /// An ability granted both exactly and through wildcard targets is listed once per grant.
+    pub fn effective_actions_for(&self, target: &UriString) -> Vec<(&Ability, ActionOrigin<'_>)> {
+        let mut actions: Vec<_> = self
+            .attenuations
+            .abilities()
+            .iter()
+            .filter_map(|(t, abilities)| {
+                let origin = if t == target {
+                    ActionOrigin::Explicit
+                } else if wildcard_covers(t.as_str(), target.as_str()) {
+                    ActionOrigin::Wildcard(t)
+                } else {
+                    return None;
+                };
+                Some(abilities.keys().map(move |ability| (ability, origin)))
+            })
+            .flatten()
+            .collect();
+        actions.sort();
+        actions
+    }
+
+    /// List every explicit grant which is already covered by the same ability on a wildcard target.
+    ///
+    /// Such grants are harmless and are kept when building a message; a strict policy can reject a set
+    /// for which this is not empty.
+    pub fn redundant_actions(&self) -> Vec<(&UriString, &Ability)> {
+        self.attenuations
+            .abilities()
+            .iter()
+            .flat_map(|(target, abilities)| {
+                let effective = self.effective_actions_for(target);
+                abilities
+                    .keys()
+                    .filter(move |ability| {
+                        effective.iter().any(|(a, origin)| {
+                            a == ability && matches!(origin, ActionOrigin::Wildcard(_))
+                        })
+                    })
+                    .map(move |ability| (target, ability))
+            })
+            .collect()
+    }
+
     /// Merge this Capabilities set with another
     pub fn merge<NB1, NB2>(self, other: Capability<NB1>) -> Capability<NB2>
     where
@@ -73,6 +162,142 @@ impl<NB> Capability<NB> {
         }
     }
 
+    /// Merge another Capabilities set into this one in place, skipping duplicate note-benes and proofs
+    ///
+    /// This adds a whole precomputed set at once. Note-benes are alternatives, so an ability granted without
+    /// note-benes by either set stays unrestricted, and otherwise the note-benes of both sets are kept.
+    pub fn merge_from(&mut self, other: &Capability<NB>) -> &mut Self
+    where
+        NB: Clone + PartialEq,
+    {
+        self.update_abilities(|caps| {
+            for (target, abilities) in other.attenuations.abilities() {
+                let existing = caps.entry(target.clone()).or_default();
+                for (ability, nbs) in abilities {
+                    match existing.get_mut(ability) {
+                        None => {
+                            existing.insert(ability.clone(), nbs.clone());
+                        }
+                        Some(ours) if ours.as_ref().is_empty() => {}
+                        Some(ours) if nbs.as_ref().is_empty() => *ours = NotaBeneCollection::new(),
+                        Some(ours) => {
+                            let new: Vec<_> = nbs
+                                .as_ref()
+                                .iter()
+                                .filter(|nb| !ours.as_ref().contains(nb))
+                                .cloned()
+                                .collect();
+                            ours.extend(new);
+                        }
+                    }
+                }
+            }
+        });
+        for proof in &other.proof {
+            if !self.proof.contains(proof) {
+                self.proof.push(*proof);
+            }
+        }
+        self
+    }
+
+    /// Union of this Capabilities set with another, as with [`Capability::merge_from`]
+    pub fn union(&self, other: &Capability<NB>) -> Self
+    where
+        NB: Clone + PartialEq,
+    {
+        let mut union = self.clone();
+        union.merge_from(other);
+        union
+    }
+
+    /// Abilities granted by this Capabilities set which are not granted, for the same target, by another.
+    ///
+    /// Targets are compared exactly and note-benes and proofs are taken from this set.
+    pub fn difference<NB1>(&self, other: &Capability<NB1>) -> Self
+    where
+        NB: Clone,
+    {
+        self.filter_abilities(|target, ability| {
+            !other
+                .attenuations
+                .abilities()
+                .get(target)
+                .is_some_and(|abilities| abilities.contains_key(ability))
+        })
+    }
+
+    /// Check if every ability granted by this Capabilities set is also granted by a parent set.
+    ///
+    /// Parent wildcard targets cover matching child targets, as with [`Capability::can_do_wildcard`], and
+    /// child targets with `.` or `..` path segments are never covered. The child's caveats must be at least as
+    /// strict as the parent's: an unrestricted parent ability covers any child note-benes, otherwise every
+    /// child note-bene must include all the fields of one of the parent's note-benes, with equal values. An
+    /// unrestricted child ability is not covered by a restricted parent.
+    pub fn is_subset(&self, parent: &Capability<NB>) -> bool
+    where
+        NB: PartialEq,
+    {
+        self.attenuations
+            .abilities()
+            .iter()
+            .all(|(target, abilities)| {
+                !has_dot_segment(target.as_str())
+                    && abilities.iter().all(|(ability, nbs)| {
+                        parent
+                            .can_do_wildcard(target, ability)
+                            .is_some_and(|allowed| caveats_covered(nbs, allowed))
+                    })
+            })
+    }
+
+    /// Check if this Capabilities set grants every ability in a requested set, as with [`Capability::is_subset`].
+    pub fn satisfies(&self, requested: &Capability<NB>) -> bool
+    where
+        NB: PartialEq,
+    {
+        requested.is_subset(self)
+    }
+
+    /// List the grants added and removed going from this set to another.
+    ///
+    /// Note-benes are not compared, and wildcard targets are compared as written rather than by what
+    /// they cover.
+    pub fn diff<NB1>(&self, other: &Capability<NB1>) -> CapabilityDiff {
+        fn missing<A, B>(from: &CapsInner<A>, within: &CapsInner<B>) -> Vec<(UriString, Ability)> {
+            from.iter()
+                .flat_map(|(target, abilities)| {
+                    abilities
+                        .keys()
+                        .filter(move |ability| {
+                            !within
+                                .get(target)
+                                .is_some_and(|abilities| abilities.contains_key(*ability))
+                        })
+                        .map(move |ability| (target.clone(), ability.clone()))
+                })
+                .collect()
+        }
+        CapabilityDiff {
+            added: missing(other.abilities(), self.abilities()),
+            removed: missing(self.abilities(), other.abilities()),
+        }
+    }
+
+    fn filter_abilities(&self, mut keep: impl FnMut(&UriString, &Ability) -> bool) -> Self
+    where
+        NB: Clone,
+    {
+        let mut filtered = self.clone();
+        filtered.update_abilities(|caps| {
+            caps.retain(|target, abilities| {
+                abilities.retain(|ability, _| keep(target, ability));
+                !abilities.is_empty()
+            })
+        });
+        filtered
+    }
+
     /// Add an allowed action for the given target, with a set of note-benes
     pub fn with_action(
         &mut self,
@@ -102,12 +327,31 @@ impl<NB> Capability<NB> {
     }
 
     /// Add a set of allowed action for the given target, with associated note-benes
+    ///
+    /// A target only exists in the set while it has at least one action, so an empty set of actions
+    /// adds nothing: there is no "presence" grant without an ability.
     pub fn with_actions(
         &mut self,
         target: UriString,
         abilities: impl IntoIterator<Item = (Ability, impl IntoIterator<Item = BTreeMap<String, NB>>)>,
     ) -> &mut Self {
-        self.attenuations.with_actions(target, abilities);
+        let mut abilities = abilities.into_iter().peekable();
+        if abilities.peek().is_some() {
+            self.attenuations.with_actions(target, abilities);
+        }
+        self
+    }
+
+    /// Add the same set of allowed actions, without note-benes, for each of the given targets
+    pub fn with_actions_on_many(
+        &mut self,
+        targets: impl IntoIterator<Item = UriString>,
+        actions: impl IntoIterator<Item = Ability>,
+    ) -> &mut Self {
+        let actions: Vec<Ability> = actions.into_iter().collect();
+        for target in targets {
+            self.with_actions(target, actions.iter().map(|a| (a.clone(), [])));
+        }
         self
     }
 
@@ -124,8 +368,54 @@ impl<NB> Capability<NB> {
         A: TryInto<Ability>,
         N: IntoIterator<Item = BTreeMap<String, NB>>,
     {
-        self.attenuations.with_actions_convert(target, abilities)?;
-        Ok(self)
+        let target = target.try_into().map_err(ConvertError::A)?;
+        let abilities = abilities
+            .into_iter()
+            .map(|(a, n)| Ok((a.try_into()?, n)))
+            .collect::<Result<Vec<(Ability, N)>, A::Error>>()
+            .map_err(ConvertError::B)?;
+        Ok(self.with_actions(target, abilities))
+    }
+
+    /// Remove a set of actions for the given target, dropping the target once it has no actions left
+    pub fn without_actions<'l>(
+        &mut self,
+        target: &UriString,
+        actions: impl IntoIterator<Item = &'l Ability>,
+    ) -> &mut Self {
+        self.update_abilities(|caps| {
+            if let Some(abilities) = caps.get_mut(target) {
+                for action in actions {
+                    abilities.remove(action);
+                }
+                if abilities.is_empty() {
+                    caps.remove(target);
+                }
+            }
+        });
+        self
+    }
+
+    /// Remove every action in the given ability namespace, across all targets
+    pub fn without_namespace(&mut self, namespace: &AbilityNamespace) -> &mut Self {
+        self.update_abilities(|caps| {
+            caps.retain(|_, abilities| {
+                abilities.retain(|ability, _| ability.namespace().as_ref() != namespace.as_ref());
+                !abilities.is_empty()
+            })
+        });
+        self
+    }
+
+    fn update_abilities(&mut self, f: impl FnOnce(&mut CapsInner<NB>)) {
+        let mut caps = std::mem::take(&mut self.attenuations).into_inner();
+        f(&mut caps);
+        self.attenuations = caps.into();
+    }
+
+    /// Read the capabilities accumulated so far, without building a message
+    pub fn capabilities(&self) -> &Capabilities<NB> {
+        &self.attenuations
     }
 
     /// Read the set of abilities granted in this capabilities set
@@ -144,6 +434,120 @@ impl<NB> Capability<NB> {
         self.attenuations.abilities_for(target)
     }
 
+    /// The number of targets with granted abilities
+    pub fn len(&self) -> usize {
+        self.attenuations.len()
+    }
+
+    /// The total number of abilities granted, counting each ability once per target
+    pub fn action_count(&self) -> usize {
+        self.attenuations
+            .abilities()
+            .values()
+            .map(BTreeMap::len)
+            .sum()
+    }
+
+    /// Check if no abilities are granted
+    ///
+    /// Targets are dropped once their last ability is removed, and an empty capabilities set adds no
+    /// resource when building a message.
+    pub fn is_empty(&self) -> bool {
+        self.attenuations.is_empty()
+    }
+
+    /// Check if any ability in the given namespace is granted on a target under the given prefix
+    ///
+    /// Wildcard targets covering the prefix count as grants under it.
+    pub fn grants_under(&self, namespace: &AbilityNamespace, target_prefix: &str) -> bool {
+        self.targets_for(namespace).any(|target| {
+            target.as_str().starts_with(target_prefix)
+                || wildcard_covers(target.as_str(), target_prefix)
+        })
+    }
+
+    /// The distinct abilities granted on any target, in canonical order
+    pub fn all_actions(&self) -> BTreeSet<&Ability> {
+        self.attenuations
+            .abilities()
+            .values()
+            .flat_map(BTreeMap::keys)
+            .collect()
+    }
+
+    /// Iterate over the targets with granted abilities, in the same order as they are encoded
+    pub fn targets(&self) -> impl Iterator<Item = &UriString> {
+        self.attenuations.abilities().keys()
+    }
+
+    /// Iterate over the targets with granted abilities in the given ability namespace, in encoding order
+    pub fn targets_for<'a>(
+        &'a self,
+        namespace: &'a AbilityNamespace,
+    ) -> impl Iterator<Item = &'a UriString> + 'a {
+        self.attenuations
+            .abilities()
+            .iter()
+            .filter(|(_, abilities)| {
+                abilities
+                    .keys()
+                    .any(|ability| ability.namespace().as_ref() == namespace.as_ref())
+            })
+            .map(|(target, _)| target)
+    }
+
+    /// Iterate over the distinct ability namespaces granted across all targets, in order
+    pub fn namespaces(&self) -> impl Iterator<Item = AbilityNamespaceRef<'_>> {
+        self.attenuations
+            .abilities()
+            .values()
+            .flat_map(|abilities| abilities.keys().map(Ability::namespace))
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+    }
+
+    /// Flatten the granted abilities into UCAN 0.9 style `{ with, can, nb }` capabilities.
+    ///
+    /// The target URI becomes `with` and the ability becomes `can`. An ability with several note-benes
+    /// produces one capability per note-bene, so no caveat is dropped.
+    pub fn to_ucan_capabilities(&self) -> Vec<UcanCapability<NB>>
+    where
+        NB: Clone,
+    {
+        self.attenuations
+            .abilities()
+            .iter()
+            .flat_map(|(target, abilities)| {
+                abilities.iter().flat_map(move |(ability, nbs)| {
+                    let nbs = nbs.as_ref();
+                    let nbs: Vec<_> = if nbs.is_empty() {
+                        vec![None]
+                    } else {
+                        nbs.iter().cloned().map(Some).collect()
+                    };
+                    nbs.into_iter().map(move |nb| UcanCapability {
+                        with: target.clone(),
+                        can: ability.clone(),
+                        nb,
+                    })
+                })
+            })
+            .collect()
+    }
+
+    /// Rebuild a capabilities set, without proofs, from flattened UCAN 0.9 style capabilities.
+    ///
+    /// This reverses [`Capability::to_ucan_capabilities`], e.g. after storing each capability as a row.
+    pub fn from_ucan_capabilities(
+        capabilities: impl IntoIterator<Item = UcanCapability<NB>>,
+    ) -> Self {
+        let mut cap = Self::new();
+        for UcanCapability { with, can, nb } in capabilities {
+            cap.with_action(with, can, nb);
+        }
+        cap
+    }
+
     /// Read the set of proofs which support the granted capabilities
     pub fn proof(&self) -> &[Cid] {
         &self.proof
@@ -151,15 +555,23 @@ impl<NB> Capability<NB> {
 
     /// Add a supporting proof CID
     pub fn with_proof(mut self, proof: &Cid) -> Self {
-        if self.proof.contains(proof) {
-            return self;
-        }
-        self.proof.push(*proof);
+        self.add_proof(proof);
         self
     }
 
     /// Add a set of supporting proofs
     pub fn with_proofs<'l>(mut self, proofs: impl IntoIterator<Item = &'l Cid>) -> Self {
+        self.add_proofs(proofs);
+        self
+    }
+
+    /// Add a supporting proof CID in place, for conditional construction
+    pub fn add_proof(&mut self, proof: &Cid) -> &mut Self {
+        self.add_proofs([proof])
+    }
+
+    /// Add a set of supporting proofs in place, for conditional construction
+    pub fn add_proofs<'l>(&mut self, proofs: impl IntoIterator<Item = &'l Cid>) -> &mut Self {
         for proof in proofs {
             if self.proof.contains(proof) {
                 continue;
@@ -171,7 +583,7 @@ impl<NB> Capability<NB> {
 
     fn to_line_groups(
         &self,
-    ) -> impl Iterator<Item = (&UriString, AbilityNamespaceRef, Vec<AbilityNameRef>)> {
+    ) -> impl Iterator<Item = (&UriString, AbilityNamespaceRef<'_>, Vec<AbilityNameRef<'_>>)> {
         self.attenuations
             .abilities()
             .iter()
@@ -193,35 +605,262 @@ impl<NB> Capability<NB> {
             })
     }
 
-    fn to_statement_lines(&self) -> impl Iterator<Item = String> + '_ {
-        self.to_line_groups().map(|(resource, namespace, names)| {
-            format!(
-                "'{}': {} for '{}'.",
-                namespace,
-                names
-                    .iter()
-                    .map(|an| format!("'{an}'"))
-                    .collect::<Vec<String>>()
-                    .join(", "),
-                resource
-            )
-        })
-    }
-
     pub fn into_inner(self) -> (Capabilities<NB>, Vec<Cid>) {
         (self.attenuations, self.proof)
     }
     /// Generate a ReCap statement from capabilities and URI (delegee).
+    ///
+    /// Targets are quoted as they are. URIs cannot contain spaces, so a target cannot imitate the
+    /// separators between abilities or clauses, which all include one.
     pub fn to_statement(&self) -> String {
-        [
-            "I further authorize the stated URI to perform the following actions on my behalf:"
-                .to_string(),
-            self.to_statement_lines()
-                .enumerate()
-                .map(|(n, line)| format!(" ({}) {line}", n + 1))
-                .collect(),
-        ]
-        .concat()
+        self.to_statement_with(&DefaultRenderer)
+    }
+
+    /// Generate a ReCap statement along with the byte range of each clause, for highlighting.
+    ///
+    /// Each clause grants the abilities in one namespace for one target, and its range excludes the
+    /// clause number.
+    pub fn to_statement_spans(&self) -> (String, Vec<StatementSpan<'_>>) {
+        let renderer = DefaultRenderer;
+        let mut statement = renderer.preamble();
+        let mut spans = Vec::new();
+        for (n, (target, namespace, names)) in self.to_line_groups().enumerate() {
+            let line = renderer.line(target, &namespace, &names);
+            statement.push_str(&renderer.numbered(n + 1, &line));
+            spans.push(StatementSpan {
+                target,
+                namespace,
+                range: statement.len() - line.len()..statement.len(),
+            });
+        }
+        (statement, spans)
+    }
+
+    /// Generate a ReCap statement from capabilities and URI (delegee), using custom wording.
+    pub fn to_statement_with<R>(&self, renderer: &R) -> String
+    where
+        R: StatementRenderer + ?Sized,
+    {
+        let mut statement = String::new();
+        // writing to a String cannot fail
+        let _ = self.write_statement_with(&mut statement, renderer);
+        statement
+    }
+
+    /// Write a ReCap statement from capabilities and URI (delegee) into a sink, without building the whole string first.
+    pub fn write_statement<W>(&self, w: &mut W) -> std::fmt::Result
+    where
+        W: std::fmt::Write + ?Sized,
+    {
+        self.write_statement_with(w, &DefaultRenderer)
+    }
+
+    /// Write a ReCap statement with custom wording into a sink, without building the whole string first.
+    pub fn write_statement_with<W, R>(&self, w: &mut W, renderer: &R) -> std::fmt::Result
+    where
+        W: std::fmt::Write + ?Sized,
+        R: StatementRenderer + ?Sized,
+    {
+        w.write_str(&renderer.preamble())?;
+        for line in self.to_numbered_lines(renderer) {
+            w.write_str(&line)?;
+        }
+        Ok(())
+    }
+
+    fn to_numbered_lines<'a, R>(&'a self, renderer: &'a R) -> impl Iterator<Item = String> + 'a
+    where
+        R: StatementRenderer + ?Sized,
+    {
+        // only grouping needs every line up front, otherwise lines are rendered as they are written
+        let lines: Box<dyn Iterator<Item = String> + 'a> = if renderer.groups_targets() {
+            // group targets by namespace and abilities, in order of first appearance
+            let mut groups: Vec<(Vec<&UriString>, AbilityNamespaceRef, Vec<AbilityNameRef>)> =
+                Vec::new();
+            for (target, namespace, names) in self.to_line_groups() {
+                match groups
+                    .iter_mut()
+                    .find(|(_, ns, n)| *ns == namespace && *n == names)
+                {
+                    Some((targets, _, _)) => targets.push(target),
+                    None => groups.push((vec![target], namespace, names)),
+                }
+            }
+            Box::new(groups.into_iter().map(|(targets, namespace, names)| {
+                renderer.grouped_line(&targets, &namespace, &names)
+            }))
+        } else {
+            Box::new(
+                self.to_line_groups()
+                    .map(|(target, namespace, names)| renderer.line(target, &namespace, &names)),
+            )
+        };
+        lines
+            .enumerate()
+            .map(|(n, line)| renderer.numbered(n + 1, &line))
+    }
+
+    fn statement_diff(&self, actual: &str) -> StatementDiff {
+        let expected = self.to_statement();
+        let offset = if strip_generated_statement(actual, &expected).is_some() {
+            None
+        } else {
+            // align on the last preamble, where the generated statement should begin
+            let trimmed = actual.trim_end();
+            let start = trimmed.rfind(STATEMENT_PREAMBLE).unwrap_or(0);
+            let generated = &trimmed.as_bytes()[start..];
+            expected
+                .bytes()
+                .zip(generated)
+                .position(|(e, a)| e != *a)
+                .or_else(|| {
+                    (expected.len() != generated.len()).then(|| expected.len().min(generated.len()))
+                })
+                // the generated statement matches but does not follow a space
+                .or(Some(0))
+        };
+        let line = offset.map(|offset| {
+            let mut end = STATEMENT_PREAMBLE.len();
+            if offset < end {
+                return 0;
+            }
+            for (n, line) in self.to_numbered_lines(&DefaultRenderer).enumerate() {
+                end += line.len();
+                if offset < end {
+                    return n + 1;
+                }
+            }
+            // the actual statement has content beyond the last line
+            self.to_line_groups().count() + 1
+        });
+        StatementDiff {
+            expected,
+            actual: actual.to_string(),
+            offset,
+            line,
+        }
+    }
+}
+
+/// Remove a generated ReCap statement from the end of a message statement, returning the custom statement before it.
+///
+/// Trailing whitespace is ignored. The generated statement must start the statement or follow a space, so
+/// that it cannot be matched against a suffix of a longer clause, and the separating space is removed.
+fn strip_generated_statement<'a>(statement: &'a str, generated: &str) -> Option<&'a str> {
+    let prefix = statement.trim_end().strip_suffix(generated)?;
+    if prefix.is_empty() {
+        Some(prefix)
+    } else {
+        prefix.strip_suffix(' ')
+    }
+}
+
+/// Check if every child note-bene is at least as strict as one of the parent's.
+fn caveats_covered<NB: PartialEq>(
+    child: &NotaBeneCollection<NB>,
+    parent: &NotaBeneCollection<NB>,
+) -> bool {
+    let (child, parent): (&[_], &[_]) = (child.as_ref(), parent.as_ref());
+    parent.is_empty()
+        || (!child.is_empty()
+            && child.iter().all(|nb| {
+                parent
+                    .iter()
+                    .any(|allowed| allowed.iter().all(|(k, v)| nb.get(k) == Some(v)))
+            }))
+}
+
+/// Check if a wildcard target, ending in `/*`, covers a target without `.` or `..` path segments.
+fn wildcard_covers(wildcard: &str, target: &str) -> bool {
+    wildcard_prefix(wildcard)
+        .is_some_and(|prefix| target.starts_with(prefix) && !has_dot_segment(target))
+}
+
+/// The prefix covered by a wildcard target ending in `/*`, without the `*`.
+pub(crate) fn wildcard_prefix(wildcard: &str) -> Option<&str> {
+    wildcard
+        .strip_suffix('*')
+        .filter(|prefix| prefix.ends_with('/') && !has_dot_segment(prefix))
+}
+
+/// Check if the path of a target has a `.` or `..` segment, percent-encoded or not.
+pub(crate) fn has_dot_segment(target: &str) -> bool {
+    target
+        .split(['?', '#'])
+        .next()
+        .unwrap_or_default()
+        .split('/')
+        .any(|segment| {
+            let segment = segment.to_ascii_lowercase().replace("%2e", ".");
+            segment == "." || segment == ".."
+        })
+}
+
+/// Where an ability which applies to a target was granted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ActionOrigin<'a> {
+    /// Granted for exactly this target.
+    Explicit,
+    /// Granted for a wildcard target covering this target.
+    Wildcard(&'a UriString),
+}
+
+/// A single capability in the UCAN 0.9 `{ with, can, nb }` shape.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct UcanCapability<NB> {
+    /// The target URI.
+    pub with: UriString,
+    /// The granted ability.
+    pub can: Ability,
+    /// Caveats restricting the ability, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nb: Option<BTreeMap<String, NB>>,
+}
+
+/// The position of one clause within a generated ReCap statement.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StatementSpan<'a> {
+    /// The target the clause grants abilities for.
+    pub target: &'a UriString,
+    /// The namespace of the abilities in the clause.
+    pub namespace: AbilityNamespaceRef<'a>,
+    /// Byte range of the clause within the statement.
+    pub range: std::ops::Range<usize>,
+}
+
+/// Comparison of the statement generated from encoded capabilities with the statement of a SIWE message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StatementDiff {
+    /// The statement generated from the encoded capabilities.
+    pub expected: String,
+    /// The statement found in the message.
+    pub actual: String,
+    /// Byte offset into `expected` of the first difference, if the statements differ.
+    pub offset: Option<usize>,
+    /// Numbered line of the generated statement containing the first difference, where 0 is the preamble.
+    pub line: Option<usize>,
+}
+
+impl StatementDiff {
+    /// Check if the generated statement was found at the end of the message statement.
+    pub fn matches(&self) -> bool {
+        self.offset.is_none()
+    }
+}
+
+/// The grants added and removed between two capabilities sets, at the granularity of a target and ability.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CapabilityDiff {
+    /// Grants only present in the new set, sorted by target then ability.
+    pub added: Vec<(UriString, Ability)>,
+    /// Grants only present in the old set, sorted by target then ability.
+    pub removed: Vec<(UriString, Ability)>,
+}
+
+impl CapabilityDiff {
+    /// Check if both sets grant the same abilities.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
     }
 }
 
@@ -231,17 +870,128 @@ where
 {
     fn encode(&self) -> Result<String, EncodingError> {
         serde_jcs::to_vec(self)
-            .map_err(EncodingError::Ser)
+            .map_err(EncodingError::from)
             .map(|bytes| base64::encode_config(bytes, base64::URL_SAFE_NO_PAD))
     }
 
+    /// A stable hex-encoded SHA-256 fingerprint of this capabilities set, independent of the statement wording.
+    ///
+    /// The digest is taken over the same JCS encoding as the resource, so equal sets share a fingerprint
+    /// regardless of the order abilities were added in. Proofs are included in the order they were added.
+    pub fn fingerprint(&self) -> Result<String, EncodingError> {
+        serde_jcs::to_vec(self)
+            .map_err(EncodingError::from)
+            .map(|bytes| hex::encode(Sha256::digest(bytes)))
+    }
+
+    fn to_uri_with_prefix(&self, prefix: &str) -> Result<UriString, EncodingError> {
+        self.encode()
+            .map(|encoded| format!("{prefix}{encoded}"))
+            .and_then(|s| s.parse().map_err(EncodingError::UriParse))
+    }
+
+    /// Check that this capabilities set can be encoded, running the same checks as [`Capability::build_message`] without building a message.
+    pub fn validate(&self) -> Result<(), EncodingError> {
+        UriString::try_from(self).map(|_| ())
+    }
+
+    /// Render the granted abilities as a CACAO/ReCap `att` object, keyed by target URI and then by ability.
+    pub fn to_attenuations(&self) -> Result<serde_json::Value, serde_json::Error> {
+        serde_json::to_value(&self.attenuations)
+    }
+
     /// Apply this capabilities set to a SIWE message by writing to it's statement and resource list
-    pub fn build_message(&self, mut message: Message) -> Result<Message, EncodingError> {
+    ///
+    /// The output does not depend on insertion order: targets and abilities are sorted lexicographically
+    /// in both the statement and the JCS-encoded resource. Proofs keep the order they were added in.
+    ///
+    /// Any capabilities already in the message are not inspected, so the message should not already carry
+    /// a ReCap resource. Use [`Capability::build_message_appending`] to add to existing capabilities.
+    ///
+    /// To assemble a message elsewhere, the resource is available as `UriString::try_from(&capability)`
+    /// and the statement as [`Capability::to_statement`].
+    ///
+    /// An empty capabilities set returns the message unchanged, so a plain login message keeps its custom
+    /// statement exactly, without a trailing separator.
+    pub fn build_message(&self, message: Message) -> Result<Message, EncodingError> {
+        self.build_message_with_prefix(message, RESOURCE_PREFIX)
+    }
+
+    /// Apply this capabilities set to a SIWE message, as with [`Capability::build_message`], failing if the message has no expiration time
+    pub fn build_message_requiring_expiration(
+        &self,
+        message: Message,
+    ) -> Result<Message, EncodingError> {
+        if message.expiration_time.is_none() {
+            return Err(EncodingError::MissingExpiration);
+        }
+        self.build_message(message)
+    }
+
+    /// Apply this capabilities set to a SIWE message, as with [`Capability::build_message`], also returning the generated statement
+    ///
+    /// The statement is `None` if there are no capabilities, in which case the message is unchanged.
+    pub fn build_message_with_statement(
+        &self,
+        message: Message,
+    ) -> Result<(Message, Option<String>), EncodingError> {
+        let message = self.build_message(message)?;
+        Ok((message, (!self.is_empty()).then(|| self.to_statement())))
+    }
+
+    /// Apply this capabilities set to a SIWE message, as with [`Capability::build_message`], failing if the encoded resource is longer than `max` bytes
+    pub fn build_message_with_max_resource_bytes(
+        &self,
+        message: Message,
+        max: usize,
+    ) -> Result<Message, EncodingError> {
+        let built = self.build_message(message)?;
+        match built.resources.last() {
+            Some(resource) if !self.is_empty() && resource.len() > max => {
+                Err(EncodingError::ResourceTooLarge {
+                    size: resource.len(),
+                    max,
+                })
+            }
+            _ => Ok(built),
+        }
+    }
+
+    /// Apply this capabilities set to a SIWE message, as with [`Capability::build_message`], using a custom resource prefix
+    pub fn build_message_with_prefix(
+        &self,
+        message: Message,
+        prefix: &str,
+    ) -> Result<Message, EncodingError> {
+        self.build(message, prefix, &DefaultRenderer)
+    }
+
+    /// Apply this capabilities set to a SIWE message, as with [`Capability::build_message`], using custom statement wording
+    pub fn build_message_with_renderer<R>(
+        &self,
+        message: Message,
+        renderer: &R,
+    ) -> Result<Message, EncodingError>
+    where
+        R: StatementRenderer + ?Sized,
+    {
+        self.build(message, RESOURCE_PREFIX, renderer)
+    }
+
+    fn build<R>(
+        &self,
+        mut message: Message,
+        prefix: &str,
+        renderer: &R,
+    ) -> Result<Message, EncodingError>
+    where
+        R: StatementRenderer + ?Sized,
+    {
         if self.attenuations.abilities().is_empty() {
             return Ok(message);
         }
-        let statement = self.to_statement();
-        let encoded: UriString = self.try_into()?;
+        let statement = self.to_statement_with(renderer);
+        let encoded = self.to_uri_with_prefix(prefix)?;
         message.resources.push(encoded);
         let m = message.statement.unwrap_or_default();
         message.statement = Some(if m.is_empty() {
@@ -257,13 +1007,212 @@ impl<NB> Capability<NB>
 where
     NB: for<'a> Deserialize<'a>,
 {
-    /// Extract the encoded capabilities from a SIWE message and ensures the correctness of the statement.
-    pub fn extract_and_verify(message: &Message) -> Result<Option<Self>, VerificationError> {
-        if let Some(c) = Self::extract(message)? {
+    /// Build a capabilities set, without proofs, from a CACAO/ReCap `att` object.
+    pub fn from_attenuations(att: serde_json::Value) -> Result<Self, serde_json::Error> {
+        Ok(Self {
+            attenuations: serde_json::from_value(att)?,
+            proof: Default::default(),
+        })
+    }
+
+    /// Extract the encoded capabilities from a SIWE message and ensures the correctness of the statement.
+    ///
+    /// Only the last resource is decoded, as EIP-5573 requires. Any earlier ReCap resources are ignored
+    /// rather than merged, so a message never grants more than its statement describes.
+    ///
+    /// The statement must end with the generated ReCap statement, either alone or following a space. Trailing
+    /// whitespace is ignored. Text before it is not inspected, so it may repeat the preamble; use
+    /// [`Capability::extract_and_verify_exact`] to reject such statements.
+    ///
+    /// The size of the payload is not bounded; use [`Capability::extract_and_verify_with_max_bytes`] for
+    /// messages from untrusted sources.
+    pub fn extract_and_verify(message: &Message) -> Result<Option<Self>, VerificationError> {
+        Self::extract_and_verify_with_prefix(message, RESOURCE_PREFIX)
+    }
+
+    /// Extract the encoded capabilities from a SIWE message and ensures the correctness of the statement,
+    /// refusing to decode a ReCap payload which would be larger than `max` bytes.
+    pub fn extract_and_verify_with_max_bytes(
+        message: &Message,
+        max: usize,
+    ) -> Result<Option<Self>, VerificationError> {
+        Self::check_size(message, max)?;
+        Self::extract_and_verify(message)
+    }
+
+    /// Extract the encoded capabilities from a SIWE message and ensures the correctness of the statement,
+    /// refusing payloads larger than `max_bytes` and capabilities sets with more than `max_targets` targets
+    /// or `max_namespaces` ability namespaces.
+    ///
+    /// The limits are checked before the statement is generated for verification.
+    pub fn extract_and_verify_with_limits(
+        message: &Message,
+        max_bytes: usize,
+        max_targets: usize,
+        max_namespaces: usize,
+    ) -> Result<Option<Self>, VerificationError> {
+        Self::check_size(message, max_bytes)?;
+        let Some(caps) = Self::extract(message)? else {
+            return Ok(None);
+        };
+        let limit = |error| DecodingError::Resource {
+            index: message.resources.len() - 1,
+            source: Box::new(error),
+        };
+        let targets = caps.len();
+        if targets > max_targets {
+            return Err(limit(DecodingError::TooManyTargets {
+                count: targets,
+                max: max_targets,
+            })
+            .into());
+        }
+        let namespaces = caps.namespaces().count();
+        if namespaces > max_namespaces {
+            return Err(limit(DecodingError::TooManyNamespaces {
+                count: namespaces,
+                max: max_namespaces,
+            })
+            .into());
+        }
+        caps.check_statement(message, &DefaultRenderer).map(Some)
+    }
+
+    fn check_size(message: &Message, max: usize) -> Result<(), DecodingError> {
+        if let Some(encoded) =
+            capability_resource(message).and_then(|u| u.as_str().strip_prefix(RESOURCE_PREFIX))
+        {
+            // unpadded base64 decodes 4 characters into 3 bytes, and a trailing n characters into n - 1
+            let size = encoded.len() / 4 * 3 + (encoded.len() % 4).saturating_sub(1);
+            if size > max {
+                return Err(DecodingError::Resource {
+                    index: message.resources.len() - 1,
+                    source: Box::new(DecodingError::TooLarge { size, max }),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Parse a SIWE message, then extract the encoded capabilities and ensure the correctness of the statement.
+    ///
+    /// CRLF line endings are read as LF. The signature must still be checked against the original message.
+    pub fn extract_and_verify_str(message: &str) -> Result<Option<Self>, VerificationError> {
+        Self::extract_and_verify(&message.replace("\r\n", "\n").parse()?)
+    }
+
+    /// Extract the encoded capabilities from a SIWE message built with a custom resource prefix and ensures the correctness of the statement.
+    pub fn extract_and_verify_with_prefix(
+        message: &Message,
+        prefix: &str,
+    ) -> Result<Option<Self>, VerificationError> {
+        Self::verify(message, prefix, &DefaultRenderer)
+    }
+
+    /// Extract the encoded capabilities from a SIWE message and ensures the statement matches the custom wording it was built with.
+    pub fn extract_and_verify_with_renderer<R>(
+        message: &Message,
+        renderer: &R,
+    ) -> Result<Option<Self>, VerificationError>
+    where
+        R: StatementRenderer + ?Sized,
+    {
+        Self::verify(message, RESOURCE_PREFIX, renderer)
+    }
+
+    fn verify<R>(
+        message: &Message,
+        prefix: &str,
+        renderer: &R,
+    ) -> Result<Option<Self>, VerificationError>
+    where
+        R: StatementRenderer + ?Sized,
+    {
+        if let Some(c) = Self::extract_with_prefix(message, prefix)? {
+            c.check_statement(message, renderer).map(Some)
+        } else {
+            // no caps
+            Ok(None)
+        }
+    }
+
+    fn check_statement<R>(self, message: &Message, renderer: &R) -> Result<Self, VerificationError>
+    where
+        R: StatementRenderer + ?Sized,
+    {
+        let expected = self.to_statement_with(renderer);
+        match message
+            .statement
+            .as_deref()
+            .and_then(|s| strip_generated_statement(s, &expected))
+        {
+            Some(_) => Ok(self),
+            None => Err(VerificationError::IncorrectStatement(expected)),
+        }
+    }
+
+    /// Extract the encoded capabilities from a SIWE message, ensuring the correctness of the statement and that the message is valid at the given time.
+    pub fn extract_and_verify_at(
+        message: &Message,
+        now: &OffsetDateTime,
+    ) -> Result<Option<Self>, VerificationError> {
+        let caps = Self::extract_and_verify(message)?;
+        if let Some(nbf) = &message.not_before {
+            if nbf.as_ref() >= now {
+                return Err(VerificationError::NotYetValid(nbf.to_string()));
+            }
+        }
+        if let Some(exp) = &message.expiration_time {
+            if exp.as_ref() < now {
+                return Err(VerificationError::Expired(exp.to_string()));
+            }
+        }
+        Ok(caps)
+    }
+
+    /// Extract the encoded capabilities from a SIWE message and ensures the correctness of the statement,
+    /// keeping only the abilities in the given namespace.
+    ///
+    /// All namespaces share the single ReCap resource, so the whole resource is still decoded and verified.
+    /// Returns `Ok(None)` if no abilities in the namespace are granted.
+    pub fn extract_namespace(
+        message: &Message,
+        namespace: &AbilityNamespace,
+    ) -> Result<Option<Self>, VerificationError> {
+        Ok(Self::extract_and_verify(message)?.and_then(|mut caps| {
+            caps.update_abilities(|caps| {
+                caps.retain(|_, abilities| {
+                    abilities
+                        .retain(|ability, _| ability.namespace().as_ref() == namespace.as_ref());
+                    !abilities.is_empty()
+                })
+            });
+            (!caps.is_empty()).then_some(caps)
+        }))
+    }
+
+    /// Extract the encoded capabilities from a SIWE message and ensures the statement is exactly as [`Capability::build_message`] would produce.
+    ///
+    /// The statement must either equal the generated ReCap statement, or be a custom statement followed by a
+    /// single space and the generated ReCap statement. The custom statement must not be empty, must not end in
+    /// whitespace and must not itself contain the ReCap statement preamble.
+    pub fn extract_and_verify_exact(message: &Message) -> Result<Option<Self>, VerificationError> {
+        if let Some(c) = Self::extract(message)? {
             let expected = c.to_statement();
-            match &message.statement {
-                Some(s) if s.ends_with(&expected) => Ok(Some(c)),
-                _ => Err(VerificationError::IncorrectStatement(expected)),
+            let exact = message.statement.as_deref().is_some_and(|s| {
+                s == expected
+                    || s.strip_suffix(&expected)
+                        .and_then(|prefix| prefix.strip_suffix(' '))
+                        .is_some_and(|prefix| {
+                            !prefix.is_empty()
+                                && !prefix.ends_with(char::is_whitespace)
+                                && !prefix.contains(STATEMENT_PREAMBLE)
+                        })
+            });
+            if exact {
+                Ok(Some(c))
+            } else {
+                Err(VerificationError::IncorrectStatement(expected))
             }
         } else {
             // no caps
@@ -271,21 +1220,389 @@ where
         }
     }
 
+    /// Compare the statement generated from the encoded capabilities with the statement of a SIWE message.
+    ///
+    /// The statements match under the same rules as [`Capability::extract_and_verify`]: trailing whitespace
+    /// is ignored and the generated statement must start the statement or follow a space. Returns `None` if
+    /// the message carries no capabilities.
+    pub fn diff_statement(message: &Message) -> Result<Option<StatementDiff>, DecodingError> {
+        Ok(Self::extract(message)?
+            .map(|c| c.statement_diff(message.statement.as_deref().unwrap_or_default())))
+    }
+
+    /// Split a SIWE message into its verified capabilities and the message as it was before they were applied.
+    ///
+    /// The ReCap resource and the generated statement are removed from the returned message, so calling
+    /// [`Capability::build_message`] on it reproduces the original. Other resources are preserved.
+    pub fn from_message(mut message: Message) -> Result<(Self, Message), VerificationError> {
+        let cap = match Self::extract_and_verify(&message)? {
+            Some(cap) => cap,
+            None => return Ok((Self::new(), message)),
+        };
+        message.resources.pop();
+        let expected = cap.to_statement();
+        message.statement = message
+            .statement
+            .as_deref()
+            .and_then(|s| strip_generated_statement(s, &expected))
+            .filter(|s| !s.is_empty())
+            .map(String::from);
+        Ok((cap, message))
+    }
+
     fn extract(message: &Message) -> Result<Option<Self>, DecodingError> {
+        Self::extract_with_prefix(message, RESOURCE_PREFIX)
+    }
+
+    fn extract_with_prefix(message: &Message, prefix: &str) -> Result<Option<Self>, DecodingError> {
         message
             .resources
             .iter()
-            .last()
-            .filter(|u| u.as_str().starts_with(RESOURCE_PREFIX))
-            .map(Self::try_from)
+            .enumerate()
+            .next_back()
+            .and_then(|(index, u)| Some((index, u.as_str().strip_prefix(prefix)?)))
+            .map(|(index, encoded)| {
+                Self::decode(encoded).map_err(|e| DecodingError::Resource {
+                    index,
+                    source: Box::new(e),
+                })
+            })
             .transpose()
     }
 
-    fn decode(encoded: &str) -> Result<Self, DecodingError> {
+    pub(crate) fn decode(encoded: &str) -> Result<Self, DecodingError> {
         base64::decode_config(encoded, base64::URL_SAFE_NO_PAD)
             .map_err(DecodingError::Base64Decode)
-            .and_then(|bytes| serde_json::from_slice(&bytes).map_err(DecodingError::De))
+            .and_then(|bytes| serde_json::from_slice(&bytes).map_err(DecodingError::from))
+    }
+}
+
+/// A JSON Schema (draft 2020-12) describing the capability object encoded in a ReCap resource.
+///
+/// The schema covers the structure of the JCS-encoded payload only; the payload is then base64url
+/// encoded without padding and prefixed with [`RESOURCE_PREFIX`].
+pub fn capability_payload_schema() -> serde_json::Value {
+    serde_json::json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "ReCap capability object",
+        "type": "object",
+        "properties": {
+            "att": {
+                "description": "Abilities granted for each target URI, with their note-benes",
+                "type": "object",
+                "additionalProperties": {
+                    "type": "object",
+                    "minProperties": 1,
+                    "propertyNames": {
+                        "pattern": "^[\\p{L}\\p{N}._+*-]+/[\\p{L}\\p{N}._+*-]+$"
+                    },
+                    "additionalProperties": {
+                        "type": "array",
+                        "minItems": 1,
+                        "items": { "type": "object" }
+                    }
+                }
+            },
+            "prf": {
+                "description": "CIDs of the delegations these capabilities are attenuated from, in base58btc",
+                "type": "array",
+                "items": {
+                    "type": "string",
+                    "pattern": "^z[1-9A-HJ-NP-Za-km-z]+$"
+                }
+            }
+        },
+        "required": ["att", "prf"],
+        "additionalProperties": false
+    })
+}
+
+/// The audience which the capabilities in a SIWE message are delegated to.
+///
+/// ReCaps treat the message `uri` as the audience: the statement authorizes "the stated URI" to
+/// perform the listed actions.
+///
+/// ```
+/// # use siwe::Message;
+/// let message: Message =
+///     include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/siwe_with_caps.txt"))
+///         .trim()
+///         .parse()?;
+/// assert_eq!(siwe_recap::delegated_to(&message).as_str(), "did:key:example");
+/// # Ok::<(), siwe::ParseError>(())
+/// ```
+pub fn delegated_to(message: &Message) -> &UriString {
+    &message.uri
+}
+
+/// Check if the audience of a SIWE message is a `did:pkh` account.
+///
+/// The statement refers only to "the stated URI", so any audience is handled the same way. The
+/// chain of a `did:pkh:eip155:<chain>:<address>` audience is not compared to the message `chain_id`,
+/// which identifies the chain of the signing address rather than of the audience.
+pub fn is_pkh_audience(message: &Message) -> bool {
+    delegated_to(message).as_str().starts_with("did:pkh:")
+}
+
+/// The ReCap resource of a SIWE message, which must be the last resource.
+pub fn capability_resource(message: &Message) -> Option<&UriString> {
+    message
+        .resources
+        .last()
+        .filter(|u| u.as_str().starts_with(RESOURCE_PREFIX))
+}
+
+/// Check if a SIWE message carries a ReCap resource which decodes. The statement is not verified.
+pub fn has_capabilities(message: &Message) -> bool {
+    capability_resource(message)
+        .is_some_and(|uri| Capability::<serde_json::Value>::try_from(uri).is_ok())
+}
+
+/// The resources of a SIWE message other than its ReCap resource.
+pub fn non_capability_resources(message: &Message) -> impl Iterator<Item = &UriString> {
+    let n = message.resources.len() - usize::from(capability_resource(message).is_some());
+    message.resources.iter().take(n)
+}
+
+/// Combine the capabilities granted by several SIWE messages, e.g. for one session.
+///
+/// Every message must pass statement verification, and messages without capabilities add nothing. When
+/// messages grant the same ability on the same target with different note-benes, all of them are kept, so
+/// the combined set allows whatever any one message allows. Proofs are combined without duplicates.
+/// Signatures are not checked.
+pub fn combine_capabilities(
+    messages: &[Message],
+) -> Result<Capability<serde_json::Value>, CombineError> {
+    let mut combined = Capability::new();
+    for (index, message) in messages.iter().enumerate() {
+        if let Some(cap) = Capability::extract_and_verify(message)
+            .map_err(|source| CombineError { index, source })?
+        {
+            combined.merge_from(&cap);
+        }
+    }
+    Ok(combined)
+}
+
+/// Verify a chain of delegations, ordered from the root, where each message only narrows its parent's capabilities.
+///
+/// Each message must pass statement verification and its capabilities must be a subset of the previous
+/// message's, as with [`Capability::is_subset`]. Signatures and proof CIDs are not checked.
+pub fn verify_delegation_chain(messages: &[Message]) -> Result<(), ChainError> {
+    let mut parent: Option<Capability<serde_json::Value>> = None;
+    for (index, message) in messages.iter().enumerate() {
+        let cap = Capability::extract_and_verify(message)
+            .map_err(|source| ChainError::Verification { index, source })?
+            .unwrap_or_default();
+        if parent.as_ref().is_some_and(|p| !cap.is_subset(p)) {
+            return Err(ChainError::NotAttenuated(index));
+        }
+        parent = Some(cap);
+    }
+    Ok(())
+}
+
+/// Extract and verify the capabilities in a SIWE message, then check if they allow an action for the specified target.
+///
+/// Granted targets ending in `/*` cover the targets under them, as with [`Capability::can_do_wildcard`].
+/// Messages without capabilities allow nothing.
+pub fn can(
+    message: &Message,
+    target: &UriString,
+    action: &Ability,
+) -> Result<bool, VerificationError> {
+    Ok(
+        Capability::<serde_json::Value>::extract_and_verify(message)?
+            .map(|cap| cap.can_do_wildcard(target, action).is_some())
+            .unwrap_or(false),
+    )
+}
+
+impl<NB> Capability<NB>
+where
+    NB: Serialize + for<'a> Deserialize<'a> + Clone + PartialEq,
+{
+    /// Apply this capabilities set to a SIWE message, merging it with any capabilities the message already carries.
+    ///
+    /// The existing capabilities are verified and replaced by a single ReCap resource and statement for the
+    /// merged set. Other resources and any custom statement are left in place.
+    pub fn build_message_appending(&self, message: Message) -> Result<Message, AppendError> {
+        let (mut existing, base) = Self::from_message(message)?;
+        existing.merge_from(self);
+        Ok(existing.build_message(base)?)
+    }
+}
+
+impl Capability<serde_json::Value> {
+    /// Merge another Capabilities set into this one, restricting abilities granted by both to the stricter caveats.
+    ///
+    /// Abilities granted by only one set are added as with [`Capability::merge_from`]. For an ability granted
+    /// by both, a side without note-benes is unrestricted, so the other side's note-benes apply. When each side
+    /// has a single note-bene they are combined: fields present on one side are kept, equal fields are kept,
+    /// and numeric fields present on both take the lower value. If any other field differs, or either side has
+    /// several alternative note-benes, the caveats cannot be intersected and both sides are kept as alternatives.
+    pub fn merge_intersecting(&mut self, other: &Capability<serde_json::Value>) -> &mut Self {
+        let mut merged = self.clone();
+        merged.merge_from(other);
+        merged.update_abilities(|caps| {
+            for (target, abilities) in caps.iter_mut() {
+                for (ability, nbs) in abilities.iter_mut() {
+                    let (Some(ours), Some(theirs)) = (
+                        self.attenuations.can_do(target, ability),
+                        other.attenuations.can_do(target, ability),
+                    ) else {
+                        continue;
+                    };
+                    let intersected = match (ours.as_ref(), theirs.as_ref()) {
+                        ([], only) | (only, []) => Some(only.to_vec()),
+                        ([a], [b]) => intersect_caveats(a, b).map(|nb| vec![nb]),
+                        _ => None,
+                    };
+                    if let Some(intersected) = intersected {
+                        *nbs = intersected.into();
+                    }
+                }
+            }
+        });
+        *self = merged;
+        self
+    }
+
+    /// Remove every ability which a policy does not allow, including through the policy's wildcard targets.
+    ///
+    /// Targets with `.` or `..` path segments are removed. Kept abilities take the stricter caveats of this
+    /// set and the policy: each note-bene is combined with each of the policy's as in
+    /// [`Capability::merge_intersecting`], and an ability is removed if no combination is possible.
+    pub fn restrict_to(&mut self, policy: &Capability<serde_json::Value>) -> &mut Self {
+        self.update_abilities(|caps| {
+            caps.retain(|target, abilities| {
+                if has_dot_segment(target.as_str()) {
+                    return false;
+                }
+                abilities.retain(|ability, nbs| {
+                    let Some(allowed) = policy.can_do_wildcard(target, ability) else {
+                        return false;
+                    };
+                    match intersect_note_benes(nbs, allowed) {
+                        Some(intersected) => {
+                            *nbs = intersected;
+                            true
+                        }
+                        None => false,
+                    }
+                });
+                !abilities.is_empty()
+            })
+        });
+        self
     }
+
+    /// Abilities granted, for the same target, by both this Capabilities set and another.
+    ///
+    /// Targets are compared exactly and proofs are taken from this set. Each ability takes the stricter
+    /// caveats of both sets, combined as in [`Capability::restrict_to`], and is dropped if no combination is
+    /// possible.
+    pub fn intersection(&self, other: &Capability<serde_json::Value>) -> Self {
+        let mut intersection = self.clone();
+        intersection.update_abilities(|caps| {
+            caps.retain(|target, abilities| {
+                abilities.retain(|ability, nbs| {
+                    match other
+                        .attenuations
+                        .can_do(target, ability)
+                        .and_then(|theirs| intersect_note_benes(nbs, theirs))
+                    {
+                        Some(intersected) => {
+                            *nbs = intersected;
+                            true
+                        }
+                        None => false,
+                    }
+                });
+                !abilities.is_empty()
+            })
+        });
+        intersection
+    }
+
+    /// The abilities still in effect at the given time, according to their `exp` caveats.
+    ///
+    /// An `exp` note-bene holds the expiry time of its grant in seconds since the Unix epoch. Note-benes
+    /// which have expired, or whose `exp` is not a number, are dropped, and so are abilities left without
+    /// any note-benes. Abilities without note-benes never expire before the message does.
+    pub fn effective_at(&self, now: &OffsetDateTime) -> Self {
+        let now = now.unix_timestamp();
+        let mut effective = self.clone();
+        effective.update_abilities(|caps| {
+            caps.retain(|_, abilities| {
+                abilities.retain(|_, nbs| {
+                    if nbs.as_ref().is_empty() {
+                        return true;
+                    }
+                    let unexpired: Vec<_> = std::mem::take(nbs)
+                        .into_iter()
+                        .filter(|nb| match nb.get("exp") {
+                            None => true,
+                            Some(exp) => exp.as_f64().is_some_and(|exp| exp > now as f64),
+                        })
+                        .collect();
+                    *nbs = unexpired.into();
+                    !nbs.as_ref().is_empty()
+                });
+                !abilities.is_empty()
+            })
+        });
+        effective
+    }
+}
+
+/// Combine two alternative sets of note-benes into the alternatives allowed by both.
+///
+/// A side without note-benes is unrestricted. Otherwise each pair of note-benes is combined with
+/// [`intersect_caveats`], and `None` is returned if no pair can be.
+fn intersect_note_benes(
+    ours: &NotaBeneCollection<serde_json::Value>,
+    theirs: &NotaBeneCollection<serde_json::Value>,
+) -> Option<NotaBeneCollection<serde_json::Value>> {
+    match (ours.as_ref(), theirs.as_ref()) {
+        ([], only) | (only, []) => Some(only.to_vec().into()),
+        (ours, theirs) => {
+            let mut intersected = Vec::new();
+            for nb in ours
+                .iter()
+                .flat_map(|a| theirs.iter().filter_map(move |b| intersect_caveats(a, b)))
+            {
+                if !intersected.contains(&nb) {
+                    intersected.push(nb);
+                }
+            }
+            (!intersected.is_empty()).then(|| intersected.into())
+        }
+    }
+}
+
+fn intersect_caveats(
+    a: &BTreeMap<String, serde_json::Value>,
+    b: &BTreeMap<String, serde_json::Value>,
+) -> Option<BTreeMap<String, serde_json::Value>> {
+    let mut merged = a.clone();
+    for (key, theirs) in b {
+        match merged.get(key) {
+            None => {
+                merged.insert(key.clone(), theirs.clone());
+            }
+            Some(ours) if ours == theirs => {}
+            Some(ours) => {
+                let lower = match (ours.as_f64(), theirs.as_f64()) {
+                    (Some(o), Some(t)) if t < o => theirs.clone(),
+                    (Some(_), Some(_)) => ours.clone(),
+                    _ => return None,
+                };
+                merged.insert(key.clone(), lower);
+            }
+        }
+    }
+    Some(merged)
 }
 
 impl<NB> Default for Capability<NB> {
@@ -294,6 +1611,51 @@ impl<NB> Default for Capability<NB> {
     }
 }
 
+/// Displays each target on its own line, followed by its abilities indented underneath.
+impl<NB> std::fmt::Display for Capability<NB> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (target, abilities) in self.attenuations.abilities() {
+            writeln!(f, "{target}")?;
+            for ability in abilities.keys() {
+                writeln!(f, "  {ability}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<NB> FromIterator<(UriString, BTreeMap<Ability, NotaBeneCollection<NB>>)> for Capability<NB> {
+    fn from_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = (UriString, BTreeMap<Ability, NotaBeneCollection<NB>>)>,
+    {
+        let mut cap = Self::new();
+        cap.extend(iter);
+        cap
+    }
+}
+
+impl<NB> Extend<(UriString, BTreeMap<Ability, NotaBeneCollection<NB>>)> for Capability<NB> {
+    fn extend<I>(&mut self, iter: I)
+    where
+        I: IntoIterator<Item = (UriString, BTreeMap<Ability, NotaBeneCollection<NB>>)>,
+    {
+        for (target, abilities) in iter {
+            self.with_actions(target, abilities);
+        }
+    }
+}
+
+impl<'a, NB> IntoIterator for &'a Capability<NB> {
+    type Item = (&'a UriString, &'a BTreeMap<Ability, NotaBeneCollection<NB>>);
+    type IntoIter =
+        std::collections::btree_map::Iter<'a, UriString, BTreeMap<Ability, NotaBeneCollection<NB>>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.attenuations.abilities().iter()
+    }
+}
+
 impl<NB> TryFrom<&UriString> for Capability<NB>
 where
     NB: for<'a> Deserialize<'a>,
@@ -313,39 +1675,221 @@ where
 {
     type Error = EncodingError;
     fn try_from(cap: &Capability<NB>) -> Result<Self, Self::Error> {
-        cap.encode()
-            .map(|encoded| format!("{RESOURCE_PREFIX}{encoded}"))
-            .and_then(|s| s.parse().map_err(EncodingError::UriParse))
+        cap.to_uri_with_prefix(RESOURCE_PREFIX)
     }
 }
 
-#[derive(thiserror::Error, Debug)]
+/// Errors decoding a ReCap resource.
+///
+/// JSON errors are kept as their messages, so that the error can be cloned.
+#[derive(thiserror::Error, Debug, Clone)]
+#[non_exhaustive]
 pub enum DecodingError {
+    /// The resource does not start with the ReCap prefix.
     #[error(
         "invalid resource prefix (expected prefix: {}, found: {0})",
         RESOURCE_PREFIX
     )]
     InvalidResourcePrefix(String),
+    /// The payload is not unpadded base64url.
     #[error("failed to decode base64 capability resource: {0}")]
     Base64Decode(#[from] base64::DecodeError),
+    /// The payload is not a valid capability object.
     #[error("failed to deserialize capability from json: {0}")]
-    De(#[from] serde_json::Error),
+    De(String),
+    /// The payload is larger than the caller allowed.
+    #[error("capability payload of {size} bytes exceeds the limit of {max}")]
+    TooLarge { size: usize, max: usize },
+    /// The capabilities set has more targets than the caller allowed.
+    #[error("capability has {count} targets, exceeding the limit of {max}")]
+    TooManyTargets { count: usize, max: usize },
+    /// The capabilities set has more ability namespaces than the caller allowed.
+    #[error("capability has {count} ability namespaces, exceeding the limit of {max}")]
+    TooManyNamespaces { count: usize, max: usize },
+    /// A statement does not follow the ReCap wording.
+    #[error("invalid recap statement: {0}")]
+    InvalidStatement(String),
+    /// The resource at `index` in the message could not be decoded.
+    #[error("failed to decode siwe message resource {index}: {source}")]
+    Resource {
+        index: usize,
+        source: Box<DecodingError>,
+    },
 }
 
-#[derive(thiserror::Error, Debug)]
+impl From<serde_json::Error> for DecodingError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::De(e.to_string())
+    }
+}
+
+impl DecodingError {
+    /// A stable identifier for the kind of error, independent of its message.
+    ///
+    /// Errors decoding a particular resource report the code of the underlying error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::InvalidResourcePrefix(_) => "invalid_resource_prefix",
+            Self::Base64Decode(_) => "invalid_base64",
+            Self::De(_) => "invalid_json",
+            Self::TooLarge { .. } => "payload_too_large",
+            Self::TooManyTargets { .. } => "too_many_targets",
+            Self::TooManyNamespaces { .. } => "too_many_namespaces",
+            Self::InvalidStatement(_) => "invalid_statement",
+            Self::Resource { source, .. } => source.code(),
+        }
+    }
+}
+
+/// Errors encoding a capabilities set into a SIWE message.
+///
+/// JSON errors are kept as their messages, so that the error can be cloned.
+#[derive(thiserror::Error, Debug, Clone)]
+#[non_exhaustive]
 pub enum EncodingError {
+    /// The encoded resource is not a valid URI, e.g. because of a custom prefix.
     #[error("unable to parse capability as a URI: {0}")]
     UriParse(#[from] iri_string::validate::Error),
+    /// The capabilities set could not be serialized.
     #[error("failed to serialize capability to json: {0}")]
-    Ser(#[from] serde_json::Error),
+    Ser(String),
+    /// The encoded resource is larger than the caller allowed.
+    #[error("encoded capability resource is {size} bytes, exceeding the limit of {max}")]
+    ResourceTooLarge { size: usize, max: usize },
+    /// The message has no expiration time, but one is required.
+    #[error("siwe message has no expiration time")]
+    MissingExpiration,
+}
+
+impl From<serde_json::Error> for EncodingError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Ser(e.to_string())
+    }
+}
+
+impl EncodingError {
+    /// A stable identifier for the kind of error, independent of its message.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::UriParse(_) => "invalid_uri",
+            Self::Ser(_) => "serialization_failed",
+            Self::ResourceTooLarge { .. } => "resource_too_large",
+            Self::MissingExpiration => "missing_expiration",
+        }
+    }
 }
 
-#[derive(thiserror::Error, Debug)]
+/// Errors extracting and verifying the capabilities in a SIWE message.
+///
+/// SIWE parsing errors are kept as their messages, so that the error can be cloned.
+#[derive(thiserror::Error, Debug, Clone)]
+#[non_exhaustive]
 pub enum VerificationError {
+    /// The message is not a valid SIWE message.
+    #[error("failed to parse siwe message: {0}")]
+    Parse(String),
+    /// The ReCap resource could not be decoded.
     #[error("error decoding capabilities: {0}")]
     Decoding(#[from] DecodingError),
+    /// The statement does not describe the encoded capabilities.
     #[error("incorrect statement in siwe message, expected to end with: {0}")]
     IncorrectStatement(String),
+    /// The message expired before the time of verification.
+    #[error("siwe message expired at {0}")]
+    Expired(String),
+    /// The message is not yet valid at the time of verification.
+    #[error("siwe message is not valid before {0}")]
+    NotYetValid(String),
+    /// The message with this nonce has been revoked.
+    #[error("siwe message with nonce {0} has been revoked")]
+    Revoked(String),
+}
+
+impl From<siwe::ParseError> for VerificationError {
+    fn from(e: siwe::ParseError) -> Self {
+        Self::Parse(e.to_string())
+    }
+}
+
+impl VerificationError {
+    /// A stable identifier for the kind of error, independent of its message.
+    ///
+    /// Decoding errors report the code of the underlying [`DecodingError`].
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Parse(_) => "invalid_message",
+            Self::Decoding(e) => e.code(),
+            Self::IncorrectStatement(_) => "incorrect_statement",
+            Self::Expired(_) => "expired",
+            Self::NotYetValid(_) => "not_yet_valid",
+            Self::Revoked(_) => "revoked",
+        }
+    }
+}
+
+/// Errors verifying a chain of delegations.
+#[derive(thiserror::Error, Debug, Clone)]
+#[non_exhaustive]
+pub enum ChainError {
+    /// The capabilities in the message at `index` are invalid.
+    #[error("invalid capabilities in message {index} of the chain: {source}")]
+    Verification {
+        index: usize,
+        source: VerificationError,
+    },
+    /// The message at this index grants more than its parent.
+    #[error("capabilities in message {0} of the chain are not attenuated from its parent")]
+    NotAttenuated(usize),
+}
+
+impl ChainError {
+    /// A stable identifier for the kind of error, independent of its message.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Verification { source, .. } => source.code(),
+            Self::NotAttenuated(_) => "not_attenuated",
+        }
+    }
+}
+
+/// A message with invalid capabilities, found while combining several messages.
+#[derive(thiserror::Error, Debug, Clone)]
+#[error("invalid capabilities in message {index}: {source}")]
+#[non_exhaustive]
+pub struct CombineError {
+    /// Position of the invalid message.
+    pub index: usize,
+    /// Why the message is invalid.
+    pub source: VerificationError,
+}
+
+impl CombineError {
+    /// A stable identifier for the kind of error, independent of its message.
+    pub fn code(&self) -> &'static str {
+        self.source.code()
+    }
+}
+
+/// Errors adding capabilities to a SIWE message which may already carry some.
+#[derive(thiserror::Error, Debug, Clone)]
+#[non_exhaustive]
+pub enum AppendError {
+    /// The capabilities already in the message are invalid.
+    #[error("existing capabilities are invalid: {0}")]
+    Verification(#[from] VerificationError),
+    /// The merged capabilities could not be encoded.
+    #[error(transparent)]
+    Encoding(#[from] EncodingError),
+}
+
+impl AppendError {
+    /// A stable identifier for the kind of error, independent of its message.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Verification(e) => e.code(),
+            Self::Encoding(e) => e.code(),
+        }
+    }
 }
 
 struct B58Cid;
@@ -389,4 +1933,1180 @@ mod test {
         let reser = serde_jcs::to_string(&cap).unwrap();
         assert_eq!(JSON_CAP.trim(), reser);
     }
+
+    #[test]
+    fn serde_roundtrip() {
+        let mut cap = Capability::<serde_json::Value>::default();
+        cap.with_action_convert("urn:credential:type:type1", "credential/present", [])
+            .unwrap()
+            .with_actions_convert(
+                "kepler:ens:example.eth://default/kv",
+                [
+                    ("kv/get", vec![]),
+                    (
+                        "kv/list",
+                        vec![[("prefix".to_string(), "public/".into())]
+                            .into_iter()
+                            .collect()],
+                    ),
+                ],
+            )
+            .unwrap();
+
+        let json = serde_json::to_value(&cap).unwrap();
+        assert!(json.get("att").is_some());
+        assert!(json.get("prf").is_some());
+        assert_eq!(
+            serde_json::from_value::<Capability<serde_json::Value>>(json).unwrap(),
+            cap
+        );
+    }
+
+    #[test]
+    fn can() {
+        let mut cap = Capability::<serde_json::Value>::new();
+        cap.with_action_convert("kepler:ens:example.eth://default/kv", "kv/get", [])
+            .unwrap();
+
+        assert!(cap
+            .can("kepler:ens:example.eth://default/kv", "kv/get")
+            .unwrap()
+            .is_some());
+        assert!(cap
+            .can("kepler:ens:example.eth://default/kv", "kv/put")
+            .unwrap()
+            .is_none());
+        assert!(cap
+            .can("kepler:ens:example.eth://default/kv/public", "kv/get")
+            .unwrap()
+            .is_none());
+        assert!(cap.can("not a uri", "kv/get").is_err());
+    }
+
+    #[test]
+    fn case_sensitive_abilities() {
+        let mut cap = Capability::<serde_json::Value>::new();
+        cap.with_action_convert("urn:credential:type:type1", "credential/present", [])
+            .unwrap();
+
+        assert!(cap
+            .can("urn:credential:type:type1", "Credential/present")
+            .unwrap()
+            .is_none());
+        assert_ne!(
+            "Credential".parse::<AbilityNamespace>().unwrap(),
+            "credential".parse::<AbilityNamespace>().unwrap()
+        );
+        assert_eq!(cap.targets_for(&"Credential".parse().unwrap()).count(), 0);
+    }
+
+    #[test]
+    fn wildcard_target() {
+        let mut cap = Capability::<serde_json::Value>::new();
+        cap.with_actions_convert(
+            "kepler:ens:example.eth://default/kv/*",
+            [("kv/list", []), ("kv/get", [])],
+        )
+        .unwrap();
+
+        assert!(cap
+            .can("kepler:ens:example.eth://default/kv/*", "kv/get")
+            .unwrap()
+            .is_some());
+        let get: Ability = "kv/get".parse().unwrap();
+        let photo: UriString = "kepler:ens:example.eth://default/kv/public/photo.jpg"
+            .parse()
+            .unwrap();
+        assert!(cap.can_do(&photo, &get).is_none());
+        assert!(cap.can_do_wildcard(&photo, &get).is_some());
+        assert!(cap
+            .can_do_wildcard(
+                &"kepler:ens:example.eth://default/kv/public"
+                    .parse()
+                    .unwrap(),
+                &"kv/put".parse().unwrap()
+            )
+            .is_none());
+        assert!(cap
+            .can_do_wildcard(
+                &"kepler:ens:example.eth://default/other/public"
+                    .parse()
+                    .unwrap(),
+                &get
+            )
+            .is_none());
+
+        let uri: UriString = (&cap).try_into().unwrap();
+        assert_eq!(Capability::try_from(&uri).unwrap(), cap);
+    }
+
+    #[test]
+    fn wildcard_rejects_traversal() {
+        let mut cap = Capability::<serde_json::Value>::new();
+        cap.with_action_convert("kepler:ens:example.eth://default/kv/public/*", "kv/get", [])
+            .unwrap();
+        let get: Ability = "kv/get".parse().unwrap();
+
+        for target in [
+            "kepler:ens:example.eth://default/kv/public/../private/secret",
+            "kepler:ens:example.eth://default/kv/public/%2e%2E/private/secret",
+            "kepler:ens:example.eth://default/kv/public/./photo.jpg",
+        ] {
+            let target: UriString = target.parse().unwrap();
+            assert!(cap.can_do_wildcard(&target, &get).is_none(), "{target}");
+            assert!(cap.effective_actions_for(&target).is_empty(), "{target}");
+        }
+    }
+
+    #[test]
+    fn wildcard_requires_segment_boundary() {
+        let mut cap = Capability::<serde_json::Value>::new();
+        cap.with_action_convert("https://example.com/foo*", "crud/read", [])
+            .unwrap()
+            .with_action_convert("credential:*", "credential/present", [])
+            .unwrap();
+        let read: Ability = "crud/read".parse().unwrap();
+        let present: Ability = "credential/present".parse().unwrap();
+
+        assert!(cap
+            .can_do_wildcard(&"https://example.com/foobar-admin".parse().unwrap(), &read)
+            .is_none());
+        assert!(cap
+            .can_do_wildcard(&"https://example.com/foo*".parse().unwrap(), &read)
+            .is_some());
+        assert!(cap
+            .can_do_wildcard(&"credential:type1".parse().unwrap(), &present)
+            .is_none());
+        assert!(cap
+            .can_do_wildcard(&"credential:*".parse().unwrap(), &present)
+            .is_some());
+    }
+
+    #[test]
+    fn effective_actions() {
+        let mut cap = Capability::<serde_json::Value>::new();
+        cap.with_actions_convert(
+            "kepler:ens:example.eth://default/kv/*",
+            [("kv/list", []), ("kv/get", [])],
+        )
+        .unwrap()
+        .with_actions_convert(
+            "kepler:ens:example.eth://default/kv/public",
+            [("kv/get", []), ("kv/put", [])],
+        )
+        .unwrap();
+
+        let wildcard: UriString = "kepler:ens:example.eth://default/kv/*".parse().unwrap();
+        let target = "kepler:ens:example.eth://default/kv/public"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            cap.effective_actions_for(&target)
+                .into_iter()
+                .map(|(ability, origin)| (ability.as_ref(), origin))
+                .collect::<Vec<_>>(),
+            [
+                ("kv/get", ActionOrigin::Explicit),
+                ("kv/get", ActionOrigin::Wildcard(&wildcard)),
+                ("kv/list", ActionOrigin::Wildcard(&wildcard)),
+                ("kv/put", ActionOrigin::Explicit),
+            ]
+        );
+    }
+
+    #[test]
+    fn from_iter() {
+        let cap: Capability<serde_json::Value> = [
+            (
+                "urn:credential:type:type1".parse().unwrap(),
+                [("credential/present".parse().unwrap(), Default::default())]
+                    .into_iter()
+                    .collect(),
+            ),
+            (
+                "credential:*".parse().unwrap(),
+                [("credential/present".parse().unwrap(), Default::default())]
+                    .into_iter()
+                    .collect(),
+            ),
+        ]
+        .into_iter()
+        .collect();
+
+        assert_eq!(cap.len(), 2);
+        let uri: UriString = (&cap).try_into().unwrap();
+        assert_eq!(Capability::try_from(&uri).unwrap(), cap);
+
+        let copy: Capability<serde_json::Value> = cap.abilities().clone().into_iter().collect();
+        assert_eq!(copy, cap);
+    }
+
+    #[test]
+    fn fingerprint() {
+        let mut a = Capability::<serde_json::Value>::default();
+        a.with_action_convert("https://example.com/pictures/", "crud/read", [])
+            .unwrap()
+            .with_action_convert("kepler:ens:example.eth://default/kv", "kv/get", [])
+            .unwrap();
+        let mut b = Capability::<serde_json::Value>::default();
+        b.with_action_convert("kepler:ens:example.eth://default/kv", "kv/get", [])
+            .unwrap()
+            .with_action_convert("https://example.com/pictures/", "crud/read", [])
+            .unwrap();
+        assert_eq!(a.fingerprint().unwrap(), b.fingerprint().unwrap());
+        assert_eq!(a.fingerprint().unwrap().len(), 64);
+
+        b.with_action_convert("kepler:ens:example.eth://default/kv", "kv/put", [])
+            .unwrap();
+        assert_ne!(a.fingerprint().unwrap(), b.fingerprint().unwrap());
+    }
+
+    #[test]
+    fn with_actions_on_many() {
+        let targets: Vec<UriString> = ["a", "b", "c"]
+            .iter()
+            .map(|t| {
+                format!("kepler:ens:example.eth://default/kv/{t}")
+                    .parse()
+                    .unwrap()
+            })
+            .collect();
+        let actions: Vec<Ability> = ["kv/list", "kv/get", "kv/metadata"]
+            .iter()
+            .map(|a| a.parse().unwrap())
+            .collect();
+        let mut cap = Capability::<serde_json::Value>::default();
+        cap.with_actions_on_many(targets.clone(), actions.clone());
+
+        assert_eq!(cap.action_count(), 9);
+        for target in &targets {
+            for action in &actions {
+                assert!(cap.can_do(target, action).is_some());
+            }
+        }
+    }
+
+    #[test]
+    fn redundant_actions() {
+        let mut cap = Capability::<serde_json::Value>::new();
+        cap.with_action_convert("https://example.com/pictures/*", "crud/read", [])
+            .unwrap()
+            .with_actions_convert(
+                "https://example.com/pictures/cat.jpg",
+                [("crud/read", []), ("crud/update", [])],
+            )
+            .unwrap();
+
+        let target: UriString = "https://example.com/pictures/cat.jpg".parse().unwrap();
+        let read: Ability = "crud/read".parse().unwrap();
+        assert_eq!(cap.redundant_actions(), vec![(&target, &read)]);
+
+        cap.without_actions(&target, [&read]);
+        assert!(cap.redundant_actions().is_empty());
+    }
+
+    #[test]
+    fn capabilities_accessor() {
+        let mut cap = Capability::<serde_json::Value>::default();
+        cap.with_actions_convert(
+            "kepler:ens:example.eth://default/kv",
+            [("kv/list", []), ("kv/get", [])],
+        )
+        .unwrap()
+        .with_action_convert("urn:credential:type:type1", "credential/present", [])
+        .unwrap();
+
+        let caps = cap.capabilities();
+        assert_eq!(caps.abilities().len(), 2);
+        assert!(caps
+            .can("kepler:ens:example.eth://default/kv", "kv/list")
+            .unwrap()
+            .is_some());
+        assert!(caps
+            .can("urn:credential:type:type1", "credential/present")
+            .unwrap()
+            .is_some());
+        assert!(caps
+            .can("kepler:ens:example.eth://default/kv", "kv/put")
+            .unwrap()
+            .is_none());
+        assert_eq!(caps, &cap.clone().into_inner().0);
+    }
+
+    #[test]
+    fn diff() {
+        let mut old = Capability::<serde_json::Value>::default();
+        old.with_actions_convert(
+            "kepler:ens:example.eth://default/kv/public",
+            [("kv/get", []), ("kv/put", [])],
+        )
+        .unwrap();
+        let mut new = old.clone();
+        new.without_actions(
+            &"kepler:ens:example.eth://default/kv/public"
+                .parse()
+                .unwrap(),
+            [&"kv/put".parse().unwrap()],
+        )
+        .with_action_convert("urn:credential:type:type1", "credential/present", [])
+        .unwrap();
+
+        let diff = old.diff(&new);
+        assert_eq!(
+            diff.added,
+            [(
+                "urn:credential:type:type1".parse().unwrap(),
+                "credential/present".parse().unwrap()
+            )]
+        );
+        assert_eq!(
+            diff.removed,
+            [(
+                "kepler:ens:example.eth://default/kv/public"
+                    .parse()
+                    .unwrap(),
+                "kv/put".parse().unwrap()
+            )]
+        );
+        assert_eq!(new.diff(&old).added, diff.removed);
+        assert!(old.diff(&old).is_empty());
+    }
+
+    #[test]
+    fn effective_at() {
+        let exp = |t: i64| {
+            [(String::from("exp"), serde_json::json!(t))]
+                .into_iter()
+                .collect::<BTreeMap<String, serde_json::Value>>()
+        };
+        let mut cap = Capability::<serde_json::Value>::default();
+        cap.with_actions_convert(
+            "kepler:ens:example.eth://default/kv",
+            [
+                ("kv/get", vec![]),
+                ("kv/delete", vec![exp(1_000)]),
+                ("kv/put", vec![exp(1_000), exp(2_000)]),
+            ],
+        )
+        .unwrap();
+
+        let before = cap.effective_at(&OffsetDateTime::from_unix_timestamp(500).unwrap());
+        assert_eq!(before, cap);
+
+        let between = cap.effective_at(&OffsetDateTime::from_unix_timestamp(1_000).unwrap());
+        let kv: UriString = "kepler:ens:example.eth://default/kv".parse().unwrap();
+        assert!(between.can_do(&kv, &"kv/get".parse().unwrap()).is_some());
+        assert!(between.can_do(&kv, &"kv/delete".parse().unwrap()).is_none());
+        assert_eq!(
+            between
+                .can_do(&kv, &"kv/put".parse().unwrap())
+                .unwrap()
+                .as_ref(),
+            [exp(2_000)]
+        );
+
+        let after = cap.effective_at(&OffsetDateTime::from_unix_timestamp(3_000).unwrap());
+        assert_eq!(after.action_count(), 1);
+    }
+
+    #[test]
+    fn statement_spans() {
+        let mut cap = Capability::<serde_json::Value>::default();
+        cap.with_actions_convert(
+            "kepler:ens:example.eth://default/kv",
+            [("kv/get", []), ("kv/list", [])],
+        )
+        .unwrap()
+        .with_action_convert("urn:credential:type:type1", "credential/present", [])
+        .unwrap();
+
+        let (statement, spans) = cap.to_statement_spans();
+        assert_eq!(statement, cap.to_statement());
+        assert_eq!(
+            spans
+                .iter()
+                .map(|span| (
+                    span.target.as_str(),
+                    span.namespace.as_ref(),
+                    &statement[span.range.clone()]
+                ))
+                .collect::<Vec<_>>(),
+            [
+                (
+                    "kepler:ens:example.eth://default/kv",
+                    "kv",
+                    "'kv': 'get', 'list' for 'kepler:ens:example.eth://default/kv'."
+                ),
+                (
+                    "urn:credential:type:type1",
+                    "credential",
+                    "'credential': 'present' for 'urn:credential:type:type1'."
+                )
+            ]
+        );
+    }
+
+    #[test]
+    fn hash() {
+        use std::collections::HashSet;
+        let mut a = Capability::<serde_json::Value>::default();
+        a.with_action_convert("https://example.com/pictures/", "crud/read", [])
+            .unwrap()
+            .with_action_convert("kepler:ens:example.eth://default/kv", "kv/get", [])
+            .unwrap();
+        let mut b = Capability::<serde_json::Value>::default();
+        b.with_action_convert("kepler:ens:example.eth://default/kv", "kv/get", [])
+            .unwrap()
+            .with_action_convert("https://example.com/pictures/", "crud/read", [])
+            .unwrap();
+        let mut c = b.clone();
+        c.with_action_convert("kepler:ens:example.eth://default/kv", "kv/put", [])
+            .unwrap();
+
+        let set: HashSet<_> = [a, b, c].into_iter().collect();
+        assert_eq!(set.len(), 2);
+
+        let namespaces: HashSet<AbilityNamespace> = ["kv", "crud", "kv"]
+            .iter()
+            .map(|ns| ns.parse().unwrap())
+            .collect();
+        assert_eq!(namespaces.len(), 2);
+    }
+
+    #[test]
+    fn all_actions() {
+        let mut cap = Capability::<serde_json::Value>::default();
+        cap.with_actions_convert(
+            "kepler:ens:example.eth://default/kv",
+            [("kv/list", []), ("kv/get", [])],
+        )
+        .unwrap()
+        .with_actions_convert(
+            "kepler:ens:example.eth://default/kv/public",
+            [("kv/get", []), ("kv/put", []), ("kv/delete", [])],
+        )
+        .unwrap();
+
+        assert_eq!(
+            cap.all_actions()
+                .into_iter()
+                .map(|a| a.as_ref())
+                .collect::<Vec<_>>(),
+            ["kv/delete", "kv/get", "kv/list", "kv/put"]
+        );
+    }
+
+    #[test]
+    fn grants_under() {
+        let mut cap = Capability::<serde_json::Value>::default();
+        cap.with_action_convert(
+            "kepler:ens:example.eth://default/kv/public/photos",
+            "kv/get",
+            [],
+        )
+        .unwrap()
+        .with_action_convert("https://example.com/pictures/*", "crud/read", [])
+        .unwrap();
+        let kv: AbilityNamespace = "kv".parse().unwrap();
+        let crud: AbilityNamespace = "crud".parse().unwrap();
+
+        assert!(cap.grants_under(&kv, "kepler:ens:example.eth://default/kv"));
+        assert!(!cap.grants_under(&kv, "kepler:ens:example.eth://default/kv/private"));
+        assert!(!cap.grants_under(&crud, "kepler:ens:example.eth://default/kv"));
+
+        // covered by a wildcard target
+        assert!(cap.grants_under(&crud, "https://example.com/pictures/cats/"));
+        assert!(cap.grants_under(&crud, "https://example.com/"));
+        assert!(!cap.grants_under(&crud, "https://example.com/videos/"));
+    }
+
+    #[test]
+    fn merge_intersecting() {
+        let kv = "kepler:ens:example.eth://default/kv";
+        let nb = |value: serde_json::Value| {
+            value
+                .as_object()
+                .unwrap()
+                .clone()
+                .into_iter()
+                .collect::<BTreeMap<String, serde_json::Value>>()
+        };
+
+        let mut a = Capability::<serde_json::Value>::new();
+        a.with_actions_convert(
+            kv,
+            [
+                (
+                    "kv/put",
+                    vec![nb(serde_json::json!({ "max": 100, "prefix": "public/" }))],
+                ),
+                ("kv/get", vec![]),
+                (
+                    "kv/list",
+                    vec![nb(serde_json::json!({ "prefix": "public/" }))],
+                ),
+            ],
+        )
+        .unwrap();
+        let mut b = Capability::<serde_json::Value>::new();
+        b.with_actions_convert(
+            kv,
+            [
+                (
+                    "kv/put",
+                    vec![nb(serde_json::json!({ "max": 10, "size": 5 }))],
+                ),
+                ("kv/get", vec![nb(serde_json::json!({ "max": 3 }))]),
+                (
+                    "kv/list",
+                    vec![nb(serde_json::json!({ "prefix": "shared/" }))],
+                ),
+                ("kv/delete", vec![]),
+            ],
+        )
+        .unwrap();
+
+        a.merge_intersecting(&b);
+        let abilities = a.abilities_for(kv).unwrap().unwrap();
+        assert_eq!(
+            abilities["kv/put"].as_ref(),
+            [nb(
+                serde_json::json!({ "max": 10, "prefix": "public/", "size": 5 })
+            )]
+        );
+        assert_eq!(
+            abilities["kv/get"].as_ref(),
+            [nb(serde_json::json!({ "max": 3 }))]
+        );
+        // conflicting caveats are kept as alternatives
+        assert_eq!(
+            abilities["kv/list"].as_ref(),
+            [
+                nb(serde_json::json!({ "prefix": "public/" })),
+                nb(serde_json::json!({ "prefix": "shared/" }))
+            ]
+        );
+        assert!(abilities["kv/delete"].as_ref().is_empty());
+    }
+
+    #[test]
+    fn add_proofs_in_place() {
+        let fixture: Capability<serde_json::Value> = serde_json::from_str(JSON_CAP).unwrap();
+        let proof = fixture.proof()[0];
+
+        let fluent = Capability::<serde_json::Value>::new().with_proof(&proof);
+
+        let mut imperative = Capability::<serde_json::Value>::new();
+        for include in [true, false, true] {
+            if include {
+                imperative.add_proof(&proof);
+            }
+        }
+        assert_eq!(imperative, fluent);
+        assert_eq!(imperative.proof(), [proof]);
+    }
+
+    #[test]
+    fn union_unrestricted() {
+        let kv = "kepler:ens:example.eth://default/kv";
+        let mut unrestricted = Capability::<serde_json::Value>::new();
+        unrestricted.with_action_convert(kv, "kv/get", []).unwrap();
+        let mut limited = Capability::<serde_json::Value>::new();
+        limited
+            .with_action_convert(
+                kv,
+                "kv/get",
+                [[("max".to_string(), 1.into())].into_iter().collect()],
+            )
+            .unwrap();
+
+        assert_eq!(unrestricted.union(&limited), unrestricted);
+        assert_eq!(limited.union(&unrestricted), unrestricted);
+    }
+
+    #[test]
+    fn intersection_caveats() {
+        let kv = "kepler:ens:example.eth://default/kv";
+        let nb = |value: serde_json::Value| -> BTreeMap<String, serde_json::Value> {
+            serde_json::from_value(value).unwrap()
+        };
+        let mut user = Capability::<serde_json::Value>::new();
+        user.with_action_convert(kv, "kv/get", [])
+            .unwrap()
+            .with_action_convert(kv, "kv/list", [nb(serde_json::json!({ "max": 5 }))])
+            .unwrap()
+            .with_action_convert(kv, "kv/put", [nb(serde_json::json!({ "path": "a" }))])
+            .unwrap();
+        let mut policy = Capability::<serde_json::Value>::new();
+        policy
+            .with_action_convert(kv, "kv/get", [nb(serde_json::json!({ "max": 1 }))])
+            .unwrap()
+            .with_action_convert(kv, "kv/list", [nb(serde_json::json!({ "max": 2 }))])
+            .unwrap()
+            .with_action_convert(kv, "kv/put", [nb(serde_json::json!({ "path": "b" }))])
+            .unwrap();
+
+        let mut expected = Capability::new();
+        expected
+            .with_action_convert(kv, "kv/get", [nb(serde_json::json!({ "max": 1 }))])
+            .unwrap()
+            .with_action_convert(kv, "kv/list", [nb(serde_json::json!({ "max": 2 }))])
+            .unwrap();
+        assert_eq!(user.intersection(&policy), expected);
+        assert_eq!(policy.intersection(&user), expected);
+        assert!(user.intersection(&policy).is_subset(&policy));
+    }
+
+    #[test]
+    fn merge_from() {
+        let target = "kepler:ens:example.eth://default/kv";
+        let limit: BTreeMap<String, serde_json::Value> =
+            [("max".to_string(), 10.into())].into_iter().collect();
+
+        let mut a = Capability::<serde_json::Value>::new();
+        a.with_actions_convert(
+            target,
+            [("kv/get", vec![]), ("kv/list", vec![limit.clone()])],
+        )
+        .unwrap();
+        let mut b = Capability::<serde_json::Value>::new();
+        b.with_actions_convert(
+            target,
+            [("kv/list", vec![limit.clone()]), ("kv/put", vec![])],
+        )
+        .unwrap();
+
+        a.merge_from(&b);
+
+        let abilities = a.abilities_for(target).unwrap().unwrap();
+        assert_eq!(
+            abilities.keys().map(|a| a.as_ref()).collect::<Vec<_>>(),
+            ["kv/get", "kv/list", "kv/put"]
+        );
+        assert_eq!(abilities["kv/list"].as_ref(), [limit].as_slice());
+    }
+
+    #[test]
+    fn merge_from_unrestricted() {
+        let target = "kepler:ens:example.eth://default/kv";
+        let limit: BTreeMap<String, serde_json::Value> =
+            [("max".to_string(), 1.into())].into_iter().collect();
+        let mut unrestricted = Capability::<serde_json::Value>::new();
+        unrestricted
+            .with_action_convert(target, "kv/get", [])
+            .unwrap();
+        let mut limited = Capability::<serde_json::Value>::new();
+        limited
+            .with_action_convert(target, "kv/get", [limit])
+            .unwrap();
+
+        for (mut a, b) in [
+            (unrestricted.clone(), &limited),
+            (limited.clone(), &unrestricted),
+        ] {
+            a.merge_from(b);
+            assert!(a
+                .can(target, "kv/get")
+                .unwrap()
+                .unwrap()
+                .as_ref()
+                .is_empty());
+        }
+    }
+
+    #[test]
+    fn attenuations() {
+        let fixture: serde_json::Value = serde_json::from_str(JSON_CAP).unwrap();
+
+        let mut cap = Capability::<serde_json::Value>::new();
+        cap.with_action_convert("http://example.com/public/photos/", "crud/delete", [])
+            .unwrap()
+            .with_actions_convert(
+                "mailto:username@example.com",
+                [
+                    (
+                        "msg/receive",
+                        vec![[
+                            ("max_count".to_string(), serde_json::json!(5)),
+                            (
+                                "templates".to_string(),
+                                serde_json::json!(["newsletter", "marketing"]),
+                            ),
+                        ]
+                        .into_iter()
+                        .collect()],
+                    ),
+                    (
+                        "msg/send",
+                        vec![
+                            [("to".to_string(), "someone@email.com".into())]
+                                .into_iter()
+                                .collect(),
+                            [("to".to_string(), "joe@email.com".into())]
+                                .into_iter()
+                                .collect(),
+                        ],
+                    ),
+                ],
+            )
+            .unwrap();
+
+        let att = cap.to_attenuations().unwrap();
+        assert_eq!(att, fixture["att"]);
+        assert_eq!(Capability::from_attenuations(att).unwrap(), cap);
+    }
+
+    #[test]
+    fn iteration() {
+        let mut cap = Capability::<serde_json::Value>::new();
+        assert!(cap.is_empty());
+        cap.with_action_convert("urn:credential:type:type1", "credential/present", [])
+            .unwrap()
+            .with_actions_convert(
+                "kepler:ens:example.eth://default/kv",
+                [("kv/get", []), ("kv/list", [])],
+            )
+            .unwrap();
+
+        assert_eq!(cap.len(), 2);
+        assert_eq!(cap.action_count(), 3);
+        assert!(!cap.is_empty());
+        assert_eq!(
+            cap.namespaces()
+                .map(|ns| ns.to_string())
+                .collect::<Vec<_>>(),
+            ["credential", "kv"]
+        );
+        assert_eq!(
+            (&cap)
+                .into_iter()
+                .map(|(target, abilities)| (target.as_str(), abilities.len()))
+                .collect::<Vec<_>>(),
+            [
+                ("kepler:ens:example.eth://default/kv", 2),
+                ("urn:credential:type:type1", 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn set_operations() {
+        let kv = "kepler:ens:example.eth://default/kv";
+        let mut a = Capability::<serde_json::Value>::new();
+        a.with_actions_convert(kv, [("kv/get", []), ("kv/list", [])])
+            .unwrap()
+            .with_action_convert("urn:credential:type:type1", "credential/present", [])
+            .unwrap();
+        let mut b = Capability::<serde_json::Value>::new();
+        b.with_actions_convert(kv, [("kv/list", []), ("kv/put", [])])
+            .unwrap()
+            .with_action_convert("mailto:username@example.com", "msg/send", [])
+            .unwrap();
+
+        let mut expected = Capability::new();
+        expected
+            .with_actions_convert(kv, [("kv/get", []), ("kv/list", []), ("kv/put", [])])
+            .unwrap()
+            .with_action_convert("urn:credential:type:type1", "credential/present", [])
+            .unwrap()
+            .with_action_convert("mailto:username@example.com", "msg/send", [])
+            .unwrap();
+        assert_eq!(a.union(&b), expected);
+
+        let mut expected = Capability::new();
+        expected.with_action_convert(kv, "kv/list", []).unwrap();
+        assert_eq!(a.intersection(&b), expected);
+
+        let mut expected = Capability::new();
+        expected
+            .with_action_convert(kv, "kv/get", [])
+            .unwrap()
+            .with_action_convert("urn:credential:type:type1", "credential/present", [])
+            .unwrap();
+        assert_eq!(a.difference(&b), expected);
+
+        let mut disjoint = Capability::<serde_json::Value>::new();
+        disjoint
+            .with_action_convert("mailto:username@example.com", "msg/send", [])
+            .unwrap();
+        assert!(a.intersection(&disjoint).is_empty());
+        assert_eq!(a.difference(&disjoint), a);
+    }
+
+    #[test]
+    fn subset() {
+        let mut parent = Capability::<serde_json::Value>::new();
+        parent
+            .with_actions_convert(
+                "kepler:ens:example.eth://default/kv/*",
+                [("kv/get", []), ("kv/list", [])],
+            )
+            .unwrap();
+
+        let mut child = Capability::<serde_json::Value>::new();
+        child
+            .with_action_convert("kepler:ens:example.eth://default/kv/public", "kv/get", [])
+            .unwrap();
+        assert!(child.is_subset(&parent));
+        assert!(Capability::<serde_json::Value>::new().is_subset(&parent));
+
+        let mut over_broad = child.clone();
+        over_broad
+            .with_action_convert("kepler:ens:example.eth://default/kv/public", "kv/put", [])
+            .unwrap();
+        assert!(!over_broad.is_subset(&parent));
+
+        let mut other_namespace = child;
+        other_namespace
+            .with_action_convert("urn:credential:type:type1", "credential/present", [])
+            .unwrap();
+        assert!(!other_namespace.is_subset(&parent));
+
+        let mut traversal = Capability::<serde_json::Value>::new();
+        traversal
+            .with_action_convert(
+                "kepler:ens:example.eth://default/kv/../private",
+                "kv/get",
+                [],
+            )
+            .unwrap();
+        assert!(!traversal.is_subset(&parent));
+    }
+
+    #[test]
+    fn subset_caveats() {
+        let with_nb = |nbs: Vec<serde_json::Value>| {
+            let mut cap = Capability::<serde_json::Value>::new();
+            cap.with_action_convert(
+                "kepler:ens:example.eth://default/kv",
+                "kv/get",
+                nbs.into_iter()
+                    .map(|nb| serde_json::from_value(nb).unwrap())
+                    .collect::<Vec<BTreeMap<String, serde_json::Value>>>(),
+            )
+            .unwrap();
+            cap
+        };
+        let unrestricted = with_nb(vec![]);
+        let limited = with_nb(vec![serde_json::json!({ "max": 1 })]);
+
+        assert!(limited.is_subset(&unrestricted));
+        assert!(!unrestricted.is_subset(&limited));
+        assert!(limited.is_subset(&limited));
+        assert!(with_nb(vec![serde_json::json!({ "max": 1, "path": "a" })]).is_subset(&limited));
+        assert!(!with_nb(vec![serde_json::json!({ "max": 2 })]).is_subset(&limited));
+        assert!(!with_nb(vec![
+            serde_json::json!({ "max": 1 }),
+            serde_json::json!({ "path": "a" })
+        ])
+        .is_subset(&limited));
+        assert!(limited.is_subset(&with_nb(vec![
+            serde_json::json!({ "path": "a" }),
+            serde_json::json!({ "max": 1 })
+        ])));
+    }
+
+    #[test]
+    fn action_input_forms() {
+        let target = "kepler:ens:example.eth://default/kv";
+        let none = Vec::<BTreeMap<String, serde_json::Value>>::new;
+
+        let mut from_strings = Capability::<serde_json::Value>::new();
+        from_strings
+            .with_actions_convert(
+                target,
+                vec![
+                    ("kv/get".to_string(), none()),
+                    ("kv/list".to_string(), none()),
+                ],
+            )
+            .unwrap();
+
+        let mut from_strs = Capability::<serde_json::Value>::new();
+        from_strs
+            .with_actions_convert(
+                target,
+                [("kv/list", none()), ("kv/get", none()), ("kv/get", none())],
+            )
+            .unwrap();
+
+        let mut from_iter = Capability::<serde_json::Value>::new();
+        from_iter
+            .with_actions_convert(
+                target,
+                ["get", "list", "get"].map(|name| (format!("kv/{name}"), none())),
+            )
+            .unwrap();
+
+        assert_eq!(from_strings, from_strs);
+        assert_eq!(from_strings, from_iter);
+        assert_eq!(from_strs.abilities_for(target).unwrap().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn display() {
+        let mut cap = Capability::<serde_json::Value>::new();
+        assert_eq!(cap.to_string(), "");
+        cap.with_action_convert("urn:credential:type:type1", "credential/present", [])
+            .unwrap()
+            .with_actions_convert(
+                "kepler:ens:example.eth://default/kv",
+                [("kv/list", []), ("kv/get", [])],
+            )
+            .unwrap();
+
+        assert_eq!(
+            cap.to_string(),
+            "kepler:ens:example.eth://default/kv\n  kv/get\n  kv/list\nurn:credential:type:type1\n  credential/present\n"
+        );
+    }
+
+    #[test]
+    fn targets() {
+        let mut cap = Capability::<serde_json::Value>::new();
+        cap.with_action_convert("kepler:ens:example.eth://default/kv/public", "kv/get", [])
+            .unwrap()
+            .with_action_convert("kepler:ens:example.eth://default/kv", "kv/list", [])
+            .unwrap()
+            .with_action_convert(
+                "kepler:ens:example.eth://default/kv/dapp-space",
+                "kv/put",
+                [],
+            )
+            .unwrap()
+            .with_action_convert("urn:credential:type:type1", "credential/present", [])
+            .unwrap();
+
+        let kv = [
+            "kepler:ens:example.eth://default/kv",
+            "kepler:ens:example.eth://default/kv/dapp-space",
+            "kepler:ens:example.eth://default/kv/public",
+        ];
+        assert_eq!(
+            cap.targets_for(&"kv".parse().unwrap())
+                .map(|t| t.as_str())
+                .collect::<Vec<_>>(),
+            kv
+        );
+        assert_eq!(cap.targets().count(), 4);
+        assert_eq!(cap.targets_for(&"msg".parse().unwrap()).count(), 0);
+    }
+
+    #[test]
+    fn invalid_actions() {
+        let mut cap = Capability::<serde_json::Value>::new();
+        for action in ["kv/get all", "kv get", "", "kv/", "kv/get/all"] {
+            assert!(matches!(
+                cap.with_action_convert("kepler:ens:example.eth://default/kv", action, []),
+                Err(ConvertError::B(_))
+            ));
+        }
+        assert!(cap.is_empty());
+    }
+
+    #[test]
+    fn ucan_capabilities() {
+        let mut cap = Capability::<serde_json::Value>::new();
+        cap.with_actions_convert(
+            "kepler:ens:example.eth://default/kv",
+            [
+                ("kv/get", vec![]),
+                (
+                    "kv/list",
+                    vec![[("prefix".to_string(), "public/".into())]
+                        .into_iter()
+                        .collect()],
+                ),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(
+            serde_json::to_value(cap.to_ucan_capabilities()).unwrap(),
+            serde_json::json!([
+                { "with": "kepler:ens:example.eth://default/kv", "can": "kv/get" },
+                {
+                    "with": "kepler:ens:example.eth://default/kv",
+                    "can": "kv/list",
+                    "nb": { "prefix": "public/" }
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn ucan_capabilities_roundtrip() {
+        let prefix = |p: &str| {
+            [("prefix".to_string(), serde_json::Value::from(p))]
+                .into_iter()
+                .collect::<BTreeMap<_, _>>()
+        };
+        let mut cap = Capability::<serde_json::Value>::new();
+        cap.with_actions_convert(
+            "kepler:ens:example.eth://default/kv",
+            [
+                ("kv/get", vec![]),
+                ("kv/list", vec![prefix("public/"), prefix("shared/")]),
+            ],
+        )
+        .unwrap()
+        .with_action_convert("urn:credential:type:type1", "credential/present", [])
+        .unwrap();
+
+        let rows = cap.to_ucan_capabilities();
+        assert_eq!(rows.len(), 4);
+        assert_eq!(Capability::from_ucan_capabilities(rows), cap);
+    }
+
+    #[test]
+    fn satisfies() {
+        let kv = "kepler:ens:example.eth://default/kv";
+        let mut requested = Capability::<serde_json::Value>::new();
+        requested
+            .with_actions_convert(kv, [("kv/get", []), ("kv/list", [])])
+            .unwrap();
+
+        assert!(requested.satisfies(&requested));
+
+        let mut under = Capability::<serde_json::Value>::new();
+        under.with_action_convert(kv, "kv/get", []).unwrap();
+        assert!(!under.satisfies(&requested));
+
+        let mut over = requested.clone();
+        over.with_action_convert(kv, "kv/put", [])
+            .unwrap()
+            .with_action_convert("urn:credential:type:type1", "credential/present", [])
+            .unwrap();
+        assert!(over.satisfies(&requested));
+    }
+
+    #[test]
+    fn canonical_payload() {
+        let mut cap = Capability::<serde_json::Value>::new();
+        cap.with_action_convert(
+            "mailto:username@example.com",
+            "msg/send",
+            [[
+                ("to".to_string(), "someone@email.com".into()),
+                ("max".to_string(), 5.into()),
+            ]
+            .into_iter()
+            .collect()],
+        )
+        .unwrap()
+        .with_action_convert("http://example.com/", "crud/delete", [])
+        .unwrap();
+
+        let uri: UriString = (&cap).try_into().unwrap();
+        let payload = base64::decode_config(
+            uri.as_str().strip_prefix(RESOURCE_PREFIX).unwrap(),
+            base64::URL_SAFE_NO_PAD,
+        )
+        .unwrap();
+        let payload = String::from_utf8(payload).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&payload).unwrap();
+
+        assert_eq!(payload, serde_jcs::to_string(&value).unwrap());
+        assert!(payload.find("\"att\"").unwrap() < payload.find("\"prf\"").unwrap());
+        assert!(payload.find("\"max\"").unwrap() < payload.find("\"to\"").unwrap());
+    }
+
+    #[test]
+    fn invalid_targets() {
+        let mut cap = Capability::<serde_json::Value>::new();
+        for target in ["", "not a uri", "kv/public"] {
+            assert!(matches!(
+                cap.with_action_convert(target, "kv/get", []),
+                Err(ConvertError::A(_))
+            ));
+        }
+        assert!(cap.is_empty());
+
+        cap.with_action_convert("type:type1", "credential/present", [])
+            .unwrap()
+            .with_action_convert("urn:credential:type:type1", "credential/present", [])
+            .unwrap();
+        assert_eq!(cap.len(), 2);
+    }
+
+    #[test]
+    fn equality() {
+        let mut a = Capability::<String>::new();
+        a.with_actions_convert(
+            "kepler:ens:example.eth://default/kv",
+            [("kv/list", []), ("kv/get", [])],
+        )
+        .unwrap()
+        .with_action_convert("urn:credential:type:type1", "credential/present", [])
+        .unwrap();
+
+        let mut b = Capability::<String>::new();
+        b.with_action_convert("urn:credential:type:type1", "credential/present", [])
+            .unwrap()
+            .with_action_convert("kepler:ens:example.eth://default/kv", "kv/get", [])
+            .unwrap()
+            .with_action_convert("kepler:ens:example.eth://default/kv", "kv/list", [])
+            .unwrap();
+
+        assert_eq!(a, b);
+        b.with_action_convert("kepler:ens:example.eth://default/kv", "kv/put", [])
+            .unwrap();
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn validate() {
+        struct Unencodable;
+
+        impl Serialize for Unencodable {
+            fn serialize<S: serde::Serializer>(&self, _: S) -> Result<S::Ok, S::Error> {
+                Err(serde::ser::Error::custom("unencodable note-bene"))
+            }
+        }
+
+        let mut cap = Capability::<Unencodable>::new();
+        cap.validate().unwrap();
+        cap.with_action_convert(
+            "kepler:ens:example.eth://default/kv",
+            "kv/get",
+            [[("nb".to_string(), Unencodable)].into_iter().collect()],
+        )
+        .unwrap();
+
+        let validated = cap.validate().unwrap_err();
+        let built = cap
+            .build_message(
+                include_str!("../tests/siwe_with_no_caps.txt")
+                    .parse()
+                    .unwrap(),
+            )
+            .unwrap_err();
+        assert!(matches!(validated, EncodingError::Ser(_)));
+        assert_eq!(validated.to_string(), built.to_string());
+    }
+
+    #[test]
+    fn serde_empty_and_unknown_fields() {
+        let empty = Capability::<serde_json::Value>::default();
+        assert_eq!(
+            serde_json::to_string(&empty).unwrap(),
+            r#"{"att":{},"prf":[]}"#
+        );
+
+        let err = serde_json::from_str::<Capability<serde_json::Value>>(
+            r#"{"att":{},"prf":[],"extra":true}"#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("unknown field `extra`"));
+    }
+
+    #[test]
+    fn version_marker_rejected() {
+        let encoded =
+            base64::encode_config(r#"{"att":{},"prf":[],"v":2}"#, base64::URL_SAFE_NO_PAD);
+        match Capability::<serde_json::Value>::decode(&encoded) {
+            Err(DecodingError::De(e)) => assert!(e.to_string().contains("unknown field `v`")),
+            other => panic!("unexpected result: {other:?}"),
+        }
+    }
 }
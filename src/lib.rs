@@ -1,12 +1,33 @@
 mod capability;
+mod resource;
+mod revocation;
+mod statement;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+mod validation;
 
-pub use capability::{Capability, DecodingError, EncodingError, VerificationError};
+pub use capability::{
+    can, capability_payload_schema, capability_resource, combine_capabilities, delegated_to,
+    has_capabilities, is_pkh_audience, non_capability_resources, verify_delegation_chain,
+    ActionOrigin, AppendError, Capability, CapabilityDiff, ChainError, CombineError, DecodingError,
+    EncodingError, StatementDiff, StatementSpan, UcanCapability, VerificationError,
+};
+pub use resource::ResourceUrn;
+pub use revocation::RevocationStore;
+pub use statement::{
+    parse_statement, DefaultRenderer, GroupedRenderer, LabelledRenderer, PriorityRenderer,
+    StatementRenderer, WildcardRenderer,
+};
 pub use ucan_capabilities_object::{
-    AbilityName, AbilityNameRef, AbilityNamespace, AbilityNamespaceRef, AbilityRef, CapsInner,
-    ConvertError, NotaBeneCollection,
+    Ability, AbilityName, AbilityNameRef, AbilityNamespace, AbilityNamespaceRef, AbilityRef,
+    CapsInner, ConvertError, NotaBeneCollection,
 };
+pub use validation::{InvalidTarget, TargetValidator};
 
 /// The prefix for a ReCap uri.
+///
+/// The rest of the uri is the capability object, serialized as canonical (JCS) JSON and encoded
+/// as unpadded base64url, as required by EIP-5573. No other payload encodings are produced or accepted.
 pub const RESOURCE_PREFIX: &str = "urn:recap:";
 
 #[cfg(test)]
@@ -14,6 +35,7 @@ mod test {
     use super::*;
     use serde_json::Value;
     use siwe::Message;
+    use testing::base_message;
 
     const SIWE_WITH_INTERLEAVED_RES: &str =
         include_str!("../tests/siwe_with_interleaved_resources.txt");
@@ -49,6 +71,25 @@ mod test {
         );
     }
 
+    #[test]
+    fn no_caps_login_statement() {
+        let mut base: Message = SIWE_NO_CAPS.trim().parse().unwrap();
+        base.statement = Some("Sign in to example.com.".into());
+
+        let mut cap = Capability::<Value>::default();
+        cap.with_action_convert("credential:*", "credential/present", [])
+            .unwrap();
+        cap.without_namespace(&"credential".parse().unwrap());
+
+        let msg = cap.build_message(base.clone()).unwrap();
+        assert_eq!(msg.statement.as_deref(), Some("Sign in to example.com."));
+        assert!(msg.to_string().contains("\n\nSign in to example.com.\n\n"));
+        assert!(Capability::<Value>::extract_and_verify(&msg)
+            .unwrap()
+            .is_none());
+        assert_eq!(cap.build_message_appending(base.clone()).unwrap(), base);
+    }
+
     #[test]
     fn build_delegation_statement_append() {
         let mut cap = Capability::<Value>::default();
@@ -182,6 +223,1532 @@ mod test {
         );
     }
 
+    #[test]
+    fn from_message_roundtrip() {
+        for fixture in [SIWE, SIWE_WITH_STATEMENT, SIWE_NO_CAPS] {
+            let msg: Message = fixture.trim().parse().unwrap();
+            let (cap, base) = Capability::<Value>::from_message(msg.clone()).unwrap();
+            assert_eq!(cap.build_message(base).unwrap(), msg);
+        }
+
+        let msg: Message = SIWE_WITH_STATEMENT.trim().parse().unwrap();
+        let (mut cap, base) = Capability::<Value>::from_message(msg).unwrap();
+        assert_eq!(base.statement.as_deref(), Some("Some custom statement."));
+
+        let mut padded: Message = SIWE_WITH_STATEMENT.trim().parse().unwrap();
+        padded.statement = padded.statement.map(|s| format!("{s}  "));
+        let (_, padded_base) = Capability::<Value>::from_message(padded).unwrap();
+        assert_eq!(padded_base.statement, base.statement);
+        assert_eq!(base.resources.len(), 1);
+
+        cap.with_action_convert("kepler:ens:example.eth://default/kv", "kv/get", [])
+            .unwrap();
+        let derived = cap.build_message(base).unwrap();
+        assert!(Capability::<Value>::extract_and_verify(&derived)
+            .unwrap()
+            .unwrap()
+            .can("kepler:ens:example.eth://default/kv", "kv/get")
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn without_actions() {
+        let target = "kepler:ens:example.eth://default/kv".parse().unwrap();
+        let mut cap = Capability::<Value>::default();
+        cap.with_actions_convert(
+            "kepler:ens:example.eth://default/kv",
+            [("kv/get", []), ("kv/list", []), ("kv/put", [])],
+        )
+        .unwrap();
+        cap.without_actions(
+            &target,
+            &["kv/list".parse().unwrap(), "kv/put".parse().unwrap()],
+        );
+
+        let msg = cap.build_message(base_message()).unwrap();
+        assert!(msg
+            .statement
+            .unwrap()
+            .ends_with("(1) 'kv': 'get' for 'kepler:ens:example.eth://default/kv'."));
+
+        cap.without_actions(&target, &["kv/get".parse().unwrap()]);
+        assert!(cap.abilities().is_empty());
+    }
+
+    #[test]
+    fn caveat_roundtrip() {
+        let mut cap = Capability::<Value>::default();
+        cap.with_action_convert(
+            "eip155:1:0x0000000000000000000000000000000000000000",
+            "eip155/transfer",
+            [[("max".to_string(), serde_json::json!(100))]
+                .into_iter()
+                .collect()],
+        )
+        .unwrap();
+
+        let msg = cap.build_message(base_message()).unwrap();
+
+        let extracted = Capability::<Value>::extract_and_verify(&msg)
+            .unwrap()
+            .unwrap();
+        assert_eq!(extracted, cap);
+        assert_eq!(
+            extracted
+                .can(
+                    "eip155:1:0x0000000000000000000000000000000000000000",
+                    "eip155/transfer"
+                )
+                .unwrap()
+                .unwrap()
+                .as_ref()[0]["max"],
+            100
+        );
+    }
+
+    #[test]
+    fn without_namespace() {
+        let mut cap = Capability::<Value>::default();
+        cap.with_action_convert("urn:credential:type:type1", "credential/present", [])
+            .unwrap()
+            .with_actions_convert(
+                "kepler:ens:example.eth://default/kv",
+                [("kv/get", []), ("kv/list", [])],
+            )
+            .unwrap();
+        cap.without_namespace(&"kv".parse().unwrap())
+            .without_namespace(&"msg".parse().unwrap());
+
+        let msg = cap.build_message(base_message()).unwrap();
+        assert!(msg
+            .statement
+            .unwrap()
+            .ends_with(": (1) 'credential': 'present' for 'urn:credential:type:type1'."));
+    }
+
+    #[test]
+    fn action_count() {
+        let msg: Message = SIWE.trim().parse().unwrap();
+        let cap = Capability::<Value>::extract_and_verify(&msg)
+            .unwrap()
+            .unwrap();
+        // 3 + 5 + 5 kv abilities and 1 credential ability
+        assert_eq!(cap.action_count(), 14);
+    }
+
+    #[test]
+    fn no_presence_grant() {
+        let mut cap = Capability::<Value>::default();
+        cap.with_actions_convert(
+            "kepler:ens:example.eth://default/kv",
+            Vec::<(&str, [_; 0])>::new(),
+        )
+        .unwrap();
+        assert!(cap.is_empty());
+
+        let message = base_message();
+        assert_eq!(cap.build_message(message.clone()).unwrap(), message);
+    }
+
+    #[test]
+    fn partition_resources() {
+        let msg: Message = SIWE_WITH_STATEMENT.trim().parse().unwrap();
+        assert_eq!(capability_resource(&msg), msg.resources.get(1));
+        assert_eq!(
+            non_capability_resources(&msg).collect::<Vec<_>>(),
+            [&msg.resources[0]]
+        );
+
+        // a ReCap resource which is not last is not recognised
+        let msg: Message = SIWE_WITH_INTERLEAVED_RES.trim().parse().unwrap();
+        assert_eq!(capability_resource(&msg), None);
+        assert_eq!(non_capability_resources(&msg).count(), 4);
+
+        let msg: Message = SIWE_NO_CAPS.parse().unwrap();
+        assert_eq!(capability_resource(&msg), None);
+        assert_eq!(non_capability_resources(&msg).count(), 0);
+    }
+
+    #[test]
+    fn resource_without_message() {
+        let msg: Message = SIWE.trim().parse().unwrap();
+        let cap = Capability::<Value>::extract_and_verify(&msg)
+            .unwrap()
+            .unwrap();
+
+        let resource = iri_string::types::UriString::try_from(&cap).unwrap();
+        assert_eq!(capability_resource(&msg), Some(&resource));
+        assert_eq!(msg.statement, Some(cap.to_statement()));
+    }
+
+    #[test]
+    fn write_statement() {
+        let msg: Message = SIWE.trim().parse().unwrap();
+        let cap = Capability::<Value>::extract_and_verify(&msg)
+            .unwrap()
+            .unwrap();
+
+        let mut written = String::new();
+        cap.write_statement(&mut written).unwrap();
+        assert_eq!(written, cap.to_statement());
+    }
+
+    #[test]
+    fn has_caps() {
+        assert!(has_capabilities(&SIWE.trim().parse().unwrap()));
+        assert!(has_capabilities(
+            &SIWE_WITH_STATEMENT.trim().parse().unwrap()
+        ));
+        assert!(!has_capabilities(&SIWE_NO_CAPS.parse().unwrap()));
+        assert!(!has_capabilities(
+            &SIWE_WITH_INTERLEAVED_RES.trim().parse().unwrap()
+        ));
+
+        let mut malformed: Message = SIWE_WITH_STATEMENT.trim().parse().unwrap();
+        malformed.resources[1] = "urn:recap:e30".parse().unwrap();
+        assert!(!has_capabilities(&malformed));
+    }
+
+    #[test]
+    fn can_query() {
+        let msg: Message = SIWE.trim().parse().unwrap();
+        let kv_public = "kepler:ens:example.eth://default/kv/public"
+            .parse()
+            .unwrap();
+        assert!(can(&msg, &kv_public, &"kv/put".parse().unwrap()).unwrap());
+        assert!(!can(
+            &msg,
+            &"kepler:ens:example.eth://default/kv".parse().unwrap(),
+            &"kv/put".parse().unwrap()
+        )
+        .unwrap());
+        assert!(!can(&msg, &kv_public, &"credential/present".parse().unwrap()).unwrap());
+
+        let mut wildcard = Capability::<Value>::default();
+        wildcard
+            .with_action_convert("kepler:ens:example.eth://default/kv/*", "kv/get", [])
+            .unwrap();
+        let msg = wildcard.build_message(base_message()).unwrap();
+        let get = "kv/get".parse().unwrap();
+        assert!(can(&msg, &kv_public, &get).unwrap());
+        assert!(!can(
+            &msg,
+            &"kepler:ens:example.eth://default/kv/../secret"
+                .parse()
+                .unwrap(),
+            &get
+        )
+        .unwrap());
+        assert!(!can(
+            &msg,
+            &"kepler:ens:example.eth://default/kvx".parse().unwrap(),
+            &get
+        )
+        .unwrap());
+
+        let no_caps: Message = SIWE_NO_CAPS.parse().unwrap();
+        assert!(!can(&no_caps, &kv_public, &"kv/put".parse().unwrap()).unwrap());
+    }
+
+    #[test]
+    fn namespace_validation() {
+        for ns in ["credential", "kepler", "kv"] {
+            ns.parse::<AbilityNamespace>().unwrap();
+        }
+        for ns in ["My Namespace", "", "foo/bar"] {
+            ns.parse::<AbilityNamespace>().unwrap_err();
+        }
+        "foo/bar/baz"
+            .parse::<ucan_capabilities_object::Ability>()
+            .unwrap_err();
+    }
+
+    #[test]
+    fn verify_exact() {
+        let msg: Message = SIWE_WITH_STATEMENT.trim().parse().unwrap();
+        assert!(Capability::<Value>::extract_and_verify_exact(&msg)
+            .unwrap()
+            .is_some());
+        let msg: Message = SIWE.trim().parse().unwrap();
+        assert!(Capability::<Value>::extract_and_verify_exact(&msg)
+            .unwrap()
+            .is_some());
+
+        let recap = msg.statement.clone().unwrap();
+        for prefix in ["garbage  ", " ", &format!("{recap} ")] {
+            let mut altered = msg.clone();
+            altered.statement = Some(format!("{prefix}{recap}"));
+            assert!(
+                Capability::<Value>::extract_and_verify(&altered).is_ok(),
+                "suffix verification should accept {prefix:?}"
+            );
+            assert!(
+                Capability::<Value>::extract_and_verify_exact(&altered).is_err(),
+                "exact verification should reject {prefix:?}"
+            );
+        }
+
+        let mut altered = msg.clone();
+        altered.statement = Some(format!("garbage{recap}"));
+        assert!(Capability::<Value>::extract_and_verify(&altered).is_err());
+        assert!(Capability::<Value>::extract_and_verify_exact(&altered).is_err());
+    }
+
+    #[test]
+    fn verify_at() {
+        let mut msg: Message = SIWE.trim().parse().unwrap();
+        msg.not_before = Some("2022-06-21T12:00:00.000Z".parse().unwrap());
+        msg.expiration_time = Some("2022-06-22T12:00:00.000Z".parse().unwrap());
+
+        let now = *"2022-06-21T18:00:00.000Z"
+            .parse::<siwe::TimeStamp>()
+            .unwrap()
+            .as_ref();
+        assert!(Capability::<Value>::extract_and_verify_at(&msg, &now)
+            .unwrap()
+            .is_some());
+
+        let before = *"2022-06-21T11:00:00.000Z"
+            .parse::<siwe::TimeStamp>()
+            .unwrap()
+            .as_ref();
+        assert!(matches!(
+            Capability::<Value>::extract_and_verify_at(&msg, &before),
+            Err(VerificationError::NotYetValid(_))
+        ));
+
+        let after = *"2022-06-23T12:00:00.000Z"
+            .parse::<siwe::TimeStamp>()
+            .unwrap()
+            .as_ref();
+        assert!(matches!(
+            Capability::<Value>::extract_and_verify_at(&msg, &after),
+            Err(VerificationError::Expired(_))
+        ));
+
+        msg.statement = Some("Wrong statement.".into());
+        assert!(matches!(
+            Capability::<Value>::extract_and_verify_at(&msg, &now),
+            Err(VerificationError::IncorrectStatement(_))
+        ));
+    }
+
+    #[test]
+    fn diff_statement() {
+        let msg: Message = SIWE.trim().parse().unwrap();
+        assert!(Capability::<Value>::diff_statement(&msg)
+            .unwrap()
+            .unwrap()
+            .matches());
+
+        let mut altered = msg.clone();
+        altered.statement = altered
+            .statement
+            .map(|s| s.replacen("'delete', 'get'", "'delete', 'put'", 2));
+        let diff = Capability::<Value>::diff_statement(&altered)
+            .unwrap()
+            .unwrap();
+        assert!(!diff.matches());
+        assert_eq!(diff.line, Some(2));
+        assert_eq!(Some(&diff.expected), msg.statement.as_ref());
+        assert!(diff.expected[diff.offset.unwrap()..].starts_with("get'"));
+
+        for statement in [
+            format!("{}  ", diff.expected),
+            format!("Custom. {}", diff.expected),
+            format!("Custom.{}", diff.expected),
+        ] {
+            let mut padded = msg.clone();
+            padded.statement = Some(statement);
+            assert_eq!(
+                Capability::<Value>::diff_statement(&padded)
+                    .unwrap()
+                    .unwrap()
+                    .matches(),
+                Capability::<Value>::extract_and_verify(&padded).is_ok()
+            );
+        }
+
+        let no_caps: Message = SIWE_NO_CAPS.parse().unwrap();
+        assert!(Capability::<Value>::diff_statement(&no_caps)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn custom_prefix() {
+        let mut cap = Capability::<Value>::default();
+        cap.with_action_convert("credential:*", "credential/present", [])
+            .unwrap();
+        let msg = cap
+            .build_message_with_prefix(base_message(), "urn:example:")
+            .unwrap();
+
+        assert!(msg.resources[0].as_str().starts_with("urn:example:"));
+        assert_eq!(
+            Capability::<Value>::extract_and_verify_with_prefix(&msg, "urn:example:")
+                .unwrap()
+                .unwrap(),
+            cap
+        );
+        assert!(Capability::<Value>::extract_and_verify(&msg)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn deterministic_output() {
+        let message = base_message();
+
+        let mut a = Capability::<Value>::default();
+        a.with_action_convert("urn:credential:type:type1", "credential/present", [])
+            .unwrap()
+            .with_actions_convert(
+                "kepler:ens:example.eth://default/kv",
+                [("kv/list", []), ("kv/get", [])],
+            )
+            .unwrap();
+        let mut b = Capability::<Value>::default();
+        b.with_actions_convert(
+            "kepler:ens:example.eth://default/kv",
+            [("kv/get", []), ("kv/list", [])],
+        )
+        .unwrap()
+        .with_action_convert("urn:credential:type:type1", "credential/present", [])
+        .unwrap();
+
+        assert_eq!(
+            a.build_message(message.clone()).unwrap().to_string(),
+            b.build_message(message).unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn duplicate_actions() {
+        let mut cap = Capability::<Value>::default();
+        cap.with_actions_convert(
+            "kepler:ens:example.eth://default/kv",
+            [("kv/get", []), ("kv/get", []), ("kv/list", [])],
+        )
+        .unwrap()
+        .with_actions_convert(
+            "kepler:ens:example.eth://default/kv",
+            [("kv/list", []), ("kv/get", [])],
+        )
+        .unwrap();
+
+        assert_eq!(
+            cap.to_statement(),
+            "I further authorize the stated URI to perform the following actions on my behalf: (1) 'kv': 'get', 'list' for 'kepler:ens:example.eth://default/kv'."
+        );
+    }
+
+    #[test]
+    fn max_resource_bytes() {
+        let message = base_message();
+        let mut cap = Capability::<Value>::default();
+        for n in 0..20 {
+            cap.with_action_convert(
+                format!("kepler:ens:example.eth://default/kv/{n}"),
+                "kv/get",
+                [],
+            )
+            .unwrap();
+        }
+
+        assert!(matches!(
+            cap.build_message_with_max_resource_bytes(message.clone(), 256),
+            Err(EncodingError::ResourceTooLarge { max: 256, .. })
+        ));
+        assert_eq!(
+            cap.build_message_with_max_resource_bytes(message.clone(), 4096)
+                .unwrap(),
+            cap.build_message(message.clone()).unwrap()
+        );
+        assert!(Capability::<Value>::default()
+            .build_message_with_max_resource_bytes(message, 0)
+            .is_ok());
+    }
+
+    #[test]
+    fn custom_renderer() {
+        use iri_string::types::UriString;
+
+        struct French;
+
+        impl StatementRenderer for French {
+            fn preamble(&self) -> String {
+                "J'autorise en outre l'URI indiquée à effectuer les actions suivantes en mon nom :"
+                    .into()
+            }
+
+            fn line(
+                &self,
+                target: &UriString,
+                namespace: &AbilityNamespaceRef<'_>,
+                names: &[AbilityNameRef<'_>],
+            ) -> String {
+                let names: Vec<String> = names.iter().map(|n| format!("'{n}'")).collect();
+                format!("'{namespace}' : {} pour '{target}'.", names.join(", "))
+            }
+        }
+
+        let mut cap = Capability::<Value>::default();
+        cap.with_action_convert("credential:*", "credential/present", [])
+            .unwrap();
+        let msg = cap
+            .build_message_with_renderer(base_message(), &French)
+            .unwrap();
+
+        assert_eq!(
+            msg.statement.as_deref(),
+            Some("J'autorise en outre l'URI indiquée à effectuer les actions suivantes en mon nom : (1) 'credential' : 'present' pour 'credential:*'.")
+        );
+        assert_eq!(
+            Capability::<Value>::extract_and_verify_with_renderer(&msg, &French)
+                .unwrap()
+                .unwrap(),
+            cap
+        );
+        assert!(Capability::<Value>::extract_and_verify(&msg).is_err());
+        assert!(
+            Capability::<Value>::extract_and_verify_with_renderer(&msg, &DefaultRenderer).is_err()
+        );
+    }
+
+    #[test]
+    fn wildcard_renderer() {
+        let mut cap = Capability::<Value>::default();
+        cap.with_action_convert("kepler:ens:example.eth://default/kv/*", "kv/get", [])
+            .unwrap()
+            .with_action_convert("credential:*", "credential/present", [])
+            .unwrap();
+        let msg = cap
+            .build_message_with_renderer(base_message(), &WildcardRenderer)
+            .unwrap();
+        let statement = msg.statement.as_deref().unwrap();
+        assert!(statement.contains(
+            "'kv': 'get' for all resources under 'kepler:ens:example.eth://default/kv/'."
+        ));
+        assert!(statement.contains("'credential': 'present' for 'credential:*'."));
+
+        let reparsed: Message = msg.to_string().parse().unwrap();
+        assert_eq!(
+            Capability::<Value>::extract_and_verify_with_renderer(&reparsed, &WildcardRenderer)
+                .unwrap()
+                .unwrap(),
+            cap
+        );
+        assert!(Capability::<Value>::extract_and_verify(&reparsed).is_err());
+    }
+
+    #[test]
+    fn numbered_list_renderer() {
+        struct NumberedList;
+
+        impl StatementRenderer for NumberedList {
+            fn numbered(&self, n: usize, line: &str) -> String {
+                format!(" {n}. {line}")
+            }
+        }
+
+        let mut cap = Capability::<Value>::default();
+        cap.with_action_convert("credential:*", "credential/present", [])
+            .unwrap()
+            .with_action_convert("kepler:ens:example.eth://default/kv", "kv/get", [])
+            .unwrap();
+        let msg = cap
+            .build_message_with_renderer(SIWE_NO_CAPS.parse().unwrap(), &NumberedList)
+            .unwrap();
+
+        assert!(msg.statement.as_ref().unwrap().ends_with(
+            "on my behalf: 1. 'credential': 'present' for 'credential:*'. 2. 'kv': 'get' for 'kepler:ens:example.eth://default/kv'."
+        ));
+        let reparsed: Message = msg.to_string().parse().unwrap();
+        assert_eq!(
+            Capability::<Value>::extract_and_verify_with_renderer(&reparsed, &NumberedList)
+                .unwrap()
+                .unwrap(),
+            cap
+        );
+        assert!(Capability::<Value>::extract_and_verify(&reparsed).is_err());
+    }
+
+    #[test]
+    fn decoding_error_source() {
+        use std::error::Error;
+
+        let mut msg: Message = SIWE_WITH_STATEMENT.trim().parse().unwrap();
+        msg.resources[1] = "urn:recap:not-base64!".parse().unwrap();
+
+        let err = Capability::<Value>::extract_and_verify(&msg).unwrap_err();
+        assert!(err.to_string().contains("resource 1"));
+        match err.source().unwrap().downcast_ref::<DecodingError>() {
+            Some(DecodingError::Resource { index: 1, source }) => {
+                assert!(matches!(**source, DecodingError::Base64Decode(_)))
+            }
+            e => panic!("unexpected error source: {e:?}"),
+        }
+    }
+
+    #[test]
+    fn build_appending() {
+        let msg: Message = SIWE_WITH_STATEMENT.trim().parse().unwrap();
+        let mut cap = Capability::<Value>::default();
+        cap.with_action_convert("kepler:ens:example.eth://default/kv", "kv/get", [])
+            .unwrap();
+
+        let appended = cap.build_message_appending(msg).unwrap();
+        assert_eq!(appended.resources.len(), 2);
+        assert!(appended.statement.as_ref().unwrap().starts_with(
+            "Some custom statement. I further authorize the stated URI to perform the following actions on my behalf: (1) 'credential': 'present' for 'credential:*'. (2) 'kv': 'get' for"
+        ));
+
+        let merged = Capability::<Value>::extract_and_verify(&appended)
+            .unwrap()
+            .unwrap();
+        assert!(merged
+            .can("credential:*", "credential/present")
+            .unwrap()
+            .is_some());
+        assert!(merged
+            .can("kepler:ens:example.eth://default/kv", "kv/get")
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn verify_str() {
+        assert!(Capability::<Value>::extract_and_verify_str(SIWE.trim())
+            .unwrap()
+            .is_some());
+        assert!(matches!(
+            Capability::<Value>::extract_and_verify_str("not a siwe message"),
+            Err(VerificationError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn delegation_chain() {
+        let message = base_message();
+        let kv = "kepler:ens:example.eth://default/kv";
+        let mut root = Capability::<Value>::default();
+        root.with_actions_convert(kv, [("kv/get", []), ("kv/list", []), ("kv/put", [])])
+            .unwrap();
+        let mut middle = Capability::<Value>::default();
+        middle
+            .with_actions_convert(kv, [("kv/get", []), ("kv/list", [])])
+            .unwrap();
+        let mut leaf = Capability::<Value>::default();
+        leaf.with_action_convert(kv, "kv/get", []).unwrap();
+        let mut over_granted = middle.clone();
+        over_granted
+            .with_action_convert(kv, "kv/delete", [])
+            .unwrap();
+
+        let build = |cap: &Capability<Value>| cap.build_message(message.clone()).unwrap();
+        verify_delegation_chain(&[build(&root), build(&middle), build(&leaf)]).unwrap();
+        assert!(matches!(
+            verify_delegation_chain(&[build(&root), build(&over_granted), build(&leaf)]),
+            Err(ChainError::NotAttenuated(1))
+        ));
+
+        let mut tampered = build(&leaf);
+        tampered.statement = Some("I grant everything.".into());
+        assert!(matches!(
+            verify_delegation_chain(&[build(&root), build(&middle), tampered]),
+            Err(ChainError::Verification { index: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn delegation_chain_escalation() {
+        let build =
+            |cap: &Capability<Value>| cap.build_message(SIWE_NO_CAPS.parse().unwrap()).unwrap();
+        let kv = "kepler:ens:example.eth://default/kv";
+
+        let mut limited = Capability::<Value>::default();
+        limited
+            .with_action_convert(
+                kv,
+                "kv/get",
+                [[("max".to_string(), serde_json::json!(1))]
+                    .into_iter()
+                    .collect()],
+            )
+            .unwrap();
+        let mut unlimited = Capability::<Value>::default();
+        unlimited.with_action_convert(kv, "kv/get", []).unwrap();
+        verify_delegation_chain(&[build(&unlimited), build(&limited)]).unwrap();
+        assert!(matches!(
+            verify_delegation_chain(&[build(&limited), build(&unlimited)]),
+            Err(ChainError::NotAttenuated(1))
+        ));
+
+        let mut public = Capability::<Value>::default();
+        public
+            .with_action_convert("kepler:ens:example.eth://default/kv/public/*", "kv/get", [])
+            .unwrap();
+        let mut escaped = Capability::<Value>::default();
+        escaped
+            .with_action_convert(
+                "kepler:ens:example.eth://default/kv/public/../private/secret",
+                "kv/get",
+                [],
+            )
+            .unwrap();
+        assert!(matches!(
+            verify_delegation_chain(&[build(&public), build(&escaped)]),
+            Err(ChainError::NotAttenuated(1))
+        ));
+    }
+
+    #[test]
+    fn build_with_statement() {
+        let mut cap = Capability::<Value>::default();
+        cap.with_action_convert("credential:*", "credential/present", [])
+            .unwrap();
+        let (msg, statement) = cap
+            .build_message_with_statement(Message {
+                statement: Some("Some custom statement.".into()),
+                ..base_message()
+            })
+            .unwrap();
+
+        assert_eq!(
+            msg.statement.unwrap(),
+            format!("Some custom statement. {}", statement.unwrap())
+        );
+
+        let (_, statement) = Capability::<Value>::default()
+            .build_message_with_statement(SIWE_NO_CAPS.parse().unwrap())
+            .unwrap();
+        assert_eq!(statement, None);
+    }
+
+    #[test]
+    fn multiple_recap_resources() {
+        let with_caps: Message = SIWE.trim().parse().unwrap();
+        let mut msg: Message = SIWE_WITH_STATEMENT.trim().parse().unwrap();
+        msg.resources
+            .insert(0, capability_resource(&with_caps).unwrap().clone());
+
+        let cap = Capability::<Value>::extract_and_verify(&msg)
+            .unwrap()
+            .unwrap();
+        assert_eq!(cap.len(), 1);
+        assert!(cap
+            .can("credential:*", "credential/present")
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn require_expiration() {
+        let mut cap = Capability::<Value>::default();
+        cap.with_action_convert("credential:*", "credential/present", [])
+            .unwrap();
+        let mut message: Message = SIWE_NO_CAPS.parse().unwrap();
+
+        assert!(matches!(
+            cap.build_message_requiring_expiration(message.clone()),
+            Err(EncodingError::MissingExpiration)
+        ));
+
+        message.expiration_time = Some("2022-06-22T12:00:00.000Z".parse().unwrap());
+        assert_eq!(
+            cap.build_message_requiring_expiration(message.clone())
+                .unwrap(),
+            cap.build_message(message).unwrap()
+        );
+    }
+
+    #[test]
+    fn max_payload_bytes() {
+        let msg: Message = SIWE.trim().parse().unwrap();
+        let encoded_len = capability_resource(&msg).unwrap().len() - RESOURCE_PREFIX.len();
+        assert!(
+            Capability::<Value>::extract_and_verify_with_max_bytes(&msg, encoded_len)
+                .unwrap()
+                .is_some()
+        );
+
+        let mut oversized = msg;
+        *oversized.resources.last_mut().unwrap() =
+            format!("{RESOURCE_PREFIX}{}", "A".repeat(1 << 20))
+                .parse()
+                .unwrap();
+        match Capability::<Value>::extract_and_verify_with_max_bytes(&oversized, 1 << 16) {
+            Err(VerificationError::Decoding(DecodingError::Resource { source, .. })) => {
+                assert!(matches!(
+                    *source,
+                    DecodingError::TooLarge { max: 65536, .. }
+                ))
+            }
+            r => panic!("oversized payload was not rejected: {r:?}"),
+        }
+    }
+
+    #[test]
+    fn malformed_payloads() {
+        let mut msg: Message = SIWE.trim().parse().unwrap();
+        for payload in [
+            "",
+            "A",
+            "AA",
+            "AAA",
+            "AAAA",
+            "e30",
+            "W10",
+            "bnVsbA",
+            "eyJhdHQiOnt9fQ",
+            "_-_-",
+            "e===",
+            "eyJhdHQiOnsiYSI6eyJiIjpbXX19LCJwcmYiOlsieiJdfQ",
+        ] {
+            *msg.resources.last_mut().unwrap() =
+                format!("{RESOURCE_PREFIX}{payload}").parse().unwrap();
+            assert!(
+                Capability::<Value>::extract_and_verify(&msg).is_err(),
+                "malformed payload {payload:?} was accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn labelled_actions() {
+        let renderer =
+            LabelledRenderer::new().with_label("kv/put".parse().unwrap(), "Upload files");
+        let mut cap = Capability::<Value>::default();
+        cap.with_actions_convert(
+            "kepler:ens:example.eth://default/kv",
+            [("kv/get", []), ("kv/put", [])],
+        )
+        .unwrap();
+
+        let msg = cap
+            .build_message_with_renderer(SIWE_NO_CAPS.parse().unwrap(), &renderer)
+            .unwrap();
+        assert!(msg.statement.as_ref().unwrap().ends_with(
+            "(1) 'kv': 'get', 'Upload files' for 'kepler:ens:example.eth://default/kv'."
+        ));
+        assert_eq!(capability_resource(&msg), Some(&(&cap).try_into().unwrap()));
+        assert_eq!(
+            Capability::<Value>::extract_and_verify_with_renderer(&msg, &renderer)
+                .unwrap()
+                .unwrap(),
+            cap
+        );
+    }
+
+    #[test]
+    fn prioritised_actions() {
+        let renderer =
+            PriorityRenderer::new(["kv/delete".parse().unwrap(), "kv/put".parse().unwrap()]);
+        let mut cap = Capability::<Value>::default();
+        cap.with_actions_convert(
+            "kepler:ens:example.eth://default/kv",
+            [
+                ("kv/get", []),
+                ("kv/put", []),
+                ("kv/delete", []),
+                ("kv/list", []),
+            ],
+        )
+        .unwrap();
+
+        let msg = cap
+            .build_message_with_renderer(SIWE_NO_CAPS.parse().unwrap(), &renderer)
+            .unwrap();
+        assert!(msg.statement.as_ref().unwrap().ends_with(
+            "(1) 'kv': 'delete', 'put', 'get', 'list' for 'kepler:ens:example.eth://default/kv'."
+        ));
+        assert_eq!(
+            capability_resource(&msg),
+            cap.build_message(SIWE_NO_CAPS.parse().unwrap())
+                .unwrap()
+                .resources
+                .last()
+        );
+        assert_eq!(
+            Capability::<Value>::extract_and_verify_with_renderer(&msg, &renderer)
+                .unwrap()
+                .unwrap(),
+            cap
+        );
+    }
+
+    #[test]
+    fn grouped_targets() {
+        let mut cap = Capability::<Value>::default();
+        for target in ["a", "b", "c"] {
+            cap.with_actions_convert(
+                format!("kepler:ens:example.eth://default/kv/{target}"),
+                [("kv/get", []), ("kv/put", [])],
+            )
+            .unwrap();
+        }
+        cap.with_actions_convert("kepler:ens:example.eth://default/kv/d", [("kv/get", [])])
+            .unwrap();
+
+        let msg = cap
+            .build_message_with_renderer(SIWE_NO_CAPS.parse().unwrap(), &GroupedRenderer)
+            .unwrap();
+        assert!(msg.statement.as_ref().unwrap().ends_with(
+            "(1) 'kv': 'get', 'put' for 'kepler:ens:example.eth://default/kv/a', 'kepler:ens:example.eth://default/kv/b', 'kepler:ens:example.eth://default/kv/c'. (2) 'kv': 'get' for 'kepler:ens:example.eth://default/kv/d'."
+        ));
+        assert_eq!(cap.targets().count(), 4);
+        assert_eq!(
+            Capability::<Value>::extract_and_verify_with_renderer(&msg, &GroupedRenderer)
+                .unwrap()
+                .unwrap(),
+            cap
+        );
+        assert!(Capability::<Value>::extract_and_verify(&msg).is_err());
+    }
+
+    #[test]
+    fn emptied_capability_not_encoded() {
+        let mut cap = Capability::<Value>::default();
+        cap.with_actions_convert("kepler:ens:example.eth://default/kv", [("kv/get", [])])
+            .unwrap()
+            .with_actions_convert("https://example.com/pictures/", [("crud/read", [])])
+            .unwrap();
+        cap.without_namespace(&"kv".parse().unwrap());
+        assert_eq!(cap.targets().count(), 1);
+        cap.without_actions(
+            &"https://example.com/pictures/".parse().unwrap(),
+            [&"crud/read".parse().unwrap()],
+        );
+        assert!(cap.is_empty());
+
+        let msg = cap.build_message(SIWE_NO_CAPS.parse().unwrap()).unwrap();
+        assert_eq!(msg, SIWE_NO_CAPS.parse().unwrap());
+        assert!(!has_capabilities(&msg));
+    }
+
+    #[test]
+    fn resource_urn() {
+        let msg: Message = SIWE.trim().parse().unwrap();
+        let resource = ResourceUrn::from_uri(capability_resource(&msg).unwrap()).unwrap();
+        assert_eq!(
+            resource.decode::<Value>().unwrap(),
+            Capability::<Value>::extract_and_verify(&msg)
+                .unwrap()
+                .unwrap()
+        );
+        assert!(resource.as_uri().as_str().ends_with(resource.payload()));
+        assert_eq!(resource.decode::<Value>().unwrap().namespaces().count(), 2);
+
+        assert!(matches!(
+            ResourceUrn::from_uri(&"https://example.com/my-web2-claim.json".parse().unwrap()),
+            Err(DecodingError::InvalidResourcePrefix(_))
+        ));
+        let invalid = ResourceUrn::from_uri(&"urn:recap:!!".parse().unwrap()).unwrap();
+        assert!(matches!(
+            invalid.decode::<Value>(),
+            Err(DecodingError::Base64Decode(_))
+        ));
+    }
+
+    #[test]
+    fn pkh_audience() {
+        let mut base: Message = SIWE_NO_CAPS.trim().parse().unwrap();
+        assert!(!is_pkh_audience(&base));
+        base.uri = "did:pkh:eip155:10:0xb9c5714089478a327f09197987f16f9e5d936e8a"
+            .parse()
+            .unwrap();
+        assert!(is_pkh_audience(&base));
+
+        let mut cap = Capability::<Value>::default();
+        cap.with_action_convert("kepler:ens:example.eth://default/kv", "kv/get", [])
+            .unwrap();
+        let msg = cap.build_message(base).unwrap();
+        let reparsed: Message = msg.to_string().parse().unwrap();
+        assert!(is_pkh_audience(&reparsed));
+        assert_eq!(
+            Capability::<Value>::extract_and_verify(&reparsed)
+                .unwrap()
+                .unwrap(),
+            cap
+        );
+    }
+
+    #[test]
+    fn extract_namespace() {
+        let msg: Message = SIWE.trim().parse().unwrap();
+        let kv = Capability::<Value>::extract_namespace(&msg, &"kv".parse().unwrap())
+            .unwrap()
+            .unwrap();
+        assert_eq!(kv.targets().count(), 3);
+        assert_eq!(kv.namespaces().count(), 1);
+        assert!(kv
+            .can("urn:credential:type:type1", "credential/present")
+            .unwrap()
+            .is_none());
+
+        let credential =
+            Capability::<Value>::extract_namespace(&msg, &"credential".parse().unwrap())
+                .unwrap()
+                .unwrap();
+        assert_eq!(credential.action_count(), 1);
+
+        assert!(
+            Capability::<Value>::extract_namespace(&msg, &"crud".parse().unwrap())
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn limits() {
+        let msg: Message = SIWE.trim().parse().unwrap();
+        assert_eq!(
+            Capability::<Value>::extract_and_verify_with_limits(&msg, 4096, 4, 2).unwrap(),
+            Capability::<Value>::extract_and_verify(&msg).unwrap()
+        );
+        match Capability::<Value>::extract_and_verify_with_limits(&msg, 4096, 3, 2) {
+            Err(VerificationError::Decoding(DecodingError::Resource { source, .. })) => {
+                assert!(matches!(
+                    *source,
+                    DecodingError::TooManyTargets { count: 4, max: 3 }
+                ))
+            }
+            other => panic!("unexpected result: {other:?}"),
+        }
+        match Capability::<Value>::extract_and_verify_with_limits(&msg, 4096, 4, 1) {
+            Err(VerificationError::Decoding(DecodingError::Resource { source, .. })) => {
+                assert!(matches!(
+                    *source,
+                    DecodingError::TooManyNamespaces { count: 2, max: 1 }
+                ))
+            }
+            other => panic!("unexpected result: {other:?}"),
+        }
+        let no_caps: Message = SIWE_NO_CAPS.trim().parse().unwrap();
+        assert!(
+            Capability::<Value>::extract_and_verify_with_limits(&no_caps, 0, 0, 0)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn payload_conforms_to_schema() {
+        let validator = jsonschema::validator_for(&capability_payload_schema()).unwrap();
+        let decode = |msg: &Message| -> Value {
+            serde_json::from_slice(
+                &base64::decode_config(
+                    ResourceUrn::from_uri(capability_resource(msg).unwrap())
+                        .unwrap()
+                        .payload(),
+                    base64::URL_SAFE_NO_PAD,
+                )
+                .unwrap(),
+            )
+            .unwrap()
+        };
+
+        let payload = decode(&SIWE.trim().parse().unwrap());
+        assert!(validator.is_valid(&payload));
+
+        let mut cap = Capability::<Value>::default();
+        cap.with_action_convert("https://example.com/pictures/", "crud/read", [])
+            .unwrap()
+            .add_proof(
+                &"bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzdi"
+                    .parse()
+                    .unwrap(),
+            );
+        assert!(validator.is_valid(&decode(
+            &cap.build_message(SIWE_NO_CAPS.parse().unwrap()).unwrap()
+        )));
+
+        let mut bad_ability = payload.clone();
+        bad_ability["att"]["https://example.com/pictures/"] = serde_json::json!({ "read": [{}] });
+        let mut extra_field = payload.clone();
+        extra_field["exp"] = 1.into();
+        let mut bad_proof = payload.clone();
+        bad_proof["prf"] =
+            serde_json::json!(["bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzdi"]);
+        let mut missing_proofs = payload;
+        missing_proofs.as_object_mut().unwrap().remove("prf");
+        for invalid in [bad_ability, extra_field, bad_proof, missing_proofs] {
+            assert!(!validator.is_valid(&invalid), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn merge_precomputed_set() {
+        let mut set = Capability::<Value>::default();
+        set.with_actions_convert(
+            "kepler:ens:example.eth://default/kv/public",
+            [("kv/get", []), ("kv/list", [])],
+        )
+        .unwrap();
+
+        let mut merged = Capability::<Value>::default();
+        merged
+            .with_action_convert("urn:credential:type:type1", "credential/present", [])
+            .unwrap()
+            .merge_from(&set);
+
+        let mut replayed = Capability::<Value>::default();
+        replayed
+            .with_action_convert("urn:credential:type:type1", "credential/present", [])
+            .unwrap()
+            .with_actions_convert(
+                "kepler:ens:example.eth://default/kv/public",
+                [("kv/get", []), ("kv/list", [])],
+            )
+            .unwrap();
+
+        assert_eq!(
+            merged.build_message(SIWE_NO_CAPS.parse().unwrap()).unwrap(),
+            replayed
+                .build_message(SIWE_NO_CAPS.parse().unwrap())
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn adversarial_statements() {
+        let mut cap = Capability::<Value>::default();
+        cap.with_action_convert("kepler:ens:example.eth://default/kv", "kv/get", [])
+            .unwrap();
+        let mut msg = cap.build_message(SIWE_NO_CAPS.parse().unwrap()).unwrap();
+        let generated = cap.to_statement();
+        let verify = |msg: &Message| Capability::<Value>::extract_and_verify(msg);
+
+        // just the generated statement
+        msg.statement = Some(generated.clone());
+        assert_eq!(verify(&msg).unwrap(), Some(cap.clone()));
+
+        // an empty custom statement
+        msg.statement = Some(format!(" {generated}"));
+        assert_eq!(verify(&msg).unwrap(), Some(cap.clone()));
+        assert!(Capability::<Value>::extract_and_verify_exact(&msg).is_err());
+
+        // the generated statement running on from other text
+        msg.statement = Some(format!("Sign in.{generated}"));
+        assert!(matches!(
+            verify(&msg),
+            Err(VerificationError::IncorrectStatement(_))
+        ));
+
+        // a crafted clause ending with the generated statement
+        msg.statement = Some(format!(
+            "I further authorize the stated URI to perform the following actions on my behalf: (1) 'kv': 'delete' for 'kepler:ens:example.eth://default/kv'.{}",
+            generated.trim_start_matches("I further authorize the stated URI to perform the following actions on my behalf:")
+        ));
+        assert!(verify(&msg).is_err());
+
+        // a repeated preamble is only rejected when verifying exactly
+        msg.statement = Some(format!("{generated} {generated}"));
+        assert!(verify(&msg).is_ok());
+        assert!(Capability::<Value>::extract_and_verify_exact(&msg).is_err());
+    }
+
+    #[test]
+    fn restrict_to_policy() {
+        let mut policy = Capability::<Value>::default();
+        policy
+            .with_action_convert("kepler:ens:example.eth://default/kv/*", "kv/get", [])
+            .unwrap();
+
+        let mut cap = Capability::<Value>::default();
+        cap.with_actions_convert(
+            "kepler:ens:example.eth://default/kv/public",
+            [("kv/get", []), ("kv/put", [])],
+        )
+        .unwrap()
+        .with_action_convert("urn:credential:type:type1", "credential/present", [])
+        .unwrap();
+        cap.restrict_to(&policy);
+
+        let msg = cap.build_message(SIWE_NO_CAPS.parse().unwrap()).unwrap();
+        let built = Capability::<Value>::extract_and_verify(&msg)
+            .unwrap()
+            .unwrap();
+        assert_eq!(built.action_count(), 1);
+        assert!(built
+            .can("kepler:ens:example.eth://default/kv/public", "kv/get")
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn restrict_to_policy_caveats() {
+        let nb = |value: Value| -> std::collections::BTreeMap<String, Value> {
+            serde_json::from_value(value).unwrap()
+        };
+        let mut policy = Capability::<Value>::default();
+        policy
+            .with_action_convert(
+                "kepler:ens:example.eth://default/kv/*",
+                "kv/get",
+                [nb(serde_json::json!({ "max": 1 }))],
+            )
+            .unwrap()
+            .with_action_convert(
+                "kepler:ens:example.eth://default/kv/*",
+                "kv/put",
+                [nb(serde_json::json!({ "path": "a" }))],
+            )
+            .unwrap();
+
+        let public = "kepler:ens:example.eth://default/kv/public";
+        let mut cap = Capability::<Value>::default();
+        cap.with_action_convert(public, "kv/get", [])
+            .unwrap()
+            .with_action_convert(public, "kv/put", [nb(serde_json::json!({ "path": "b" }))])
+            .unwrap()
+            .with_action_convert(
+                "kepler:ens:example.eth://default/kv/public/../private",
+                "kv/get",
+                [],
+            )
+            .unwrap();
+        cap.restrict_to(&policy);
+
+        assert_eq!(cap.action_count(), 1);
+        assert_eq!(
+            cap.can(public, "kv/get").unwrap().unwrap().as_ref(),
+            [nb(serde_json::json!({ "max": 1 }))]
+        );
+        assert!(cap.is_subset(&policy));
+    }
+
+    #[test]
+    fn revocation_store() {
+        use std::future::Future;
+        use std::sync::Arc;
+        use std::task::{Context, Poll, Wake, Waker};
+
+        struct Revoked(&'static str);
+        impl RevocationStore for Revoked {
+            async fn is_revoked(&self, nonce: &str) -> bool {
+                nonce == self.0
+            }
+        }
+
+        struct NoopWaker;
+        impl Wake for NoopWaker {
+            fn wake(self: Arc<Self>) {}
+        }
+
+        fn block_on<F: Future>(future: F) -> F::Output {
+            let mut future = std::pin::pin!(future);
+            let waker = Waker::from(Arc::new(NoopWaker));
+            let mut cx = Context::from_waker(&waker);
+            loop {
+                if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                    return output;
+                }
+            }
+        }
+
+        fn assert_send<T: Send>(_: &T) {}
+
+        let msg: Message = SIWE.trim().parse().unwrap();
+        assert_send(&Capability::<Value>::extract_and_verify_with_store(
+            &msg,
+            &Revoked("othernonce"),
+        ));
+        assert!(block_on(Capability::<Value>::extract_and_verify_with_store(
+            &msg,
+            &Revoked("othernonce")
+        ))
+        .unwrap()
+        .is_some());
+        assert!(matches!(
+            block_on(Capability::<Value>::extract_and_verify_with_store(
+                &msg,
+                &Revoked("mynonce1")
+            )),
+            Err(VerificationError::Revoked(nonce)) if nonce == "mynonce1"
+        ));
+    }
+
+    #[test]
+    fn target_validator() {
+        let validator = TargetValidator::new()
+            .with_target_validator("kv".parse().unwrap(), |target| {
+                target.as_str().starts_with("kepler:")
+            });
+
+        let mut cap = Capability::<Value>::default();
+        cap.with_actions_validated(
+            "kepler:ens:example.eth://default/kv".parse().unwrap(),
+            [("kv/get".parse().unwrap(), NotaBeneCollection::new())],
+            &validator,
+        )
+        .unwrap();
+        let err = cap
+            .with_actions_validated(
+                "https://example.com/kv".parse().unwrap(),
+                [("kv/put".parse().unwrap(), NotaBeneCollection::new())],
+                &validator,
+            )
+            .unwrap_err();
+        assert_eq!(err.namespace.as_ref(), "kv");
+        assert_eq!(cap.action_count(), 1);
+
+        // namespaces without a validator are not restricted
+        cap.with_actions_validated(
+            "https://example.com/pictures/".parse().unwrap(),
+            [("crud/read".parse().unwrap(), NotaBeneCollection::new())],
+            &validator,
+        )
+        .unwrap();
+        assert!(validator.check_all(&cap).is_ok());
+
+        cap.with_action_convert("https://example.com/kv", "kv/put", [])
+            .unwrap();
+        assert!(validator.check_all(&cap).is_err());
+    }
+
+    #[test]
+    fn whitespace_normalization() {
+        let crlf = SIWE.trim().replace('\n', "\r\n");
+        assert_eq!(
+            Capability::<Value>::extract_and_verify_str(&crlf).unwrap(),
+            Capability::<Value>::extract_and_verify_str(SIWE.trim()).unwrap()
+        );
+
+        let mut msg: Message = SIWE.trim().parse().unwrap();
+        msg.statement = msg.statement.map(|s| format!("{s}  "));
+        assert!(Capability::<Value>::extract_and_verify(&msg)
+            .unwrap()
+            .is_some());
+        assert!(Capability::<Value>::extract_and_verify_exact(&msg).is_err());
+    }
+
+    #[test]
+    fn combine_messages() {
+        let build =
+            |cap: &Capability<Value>| cap.build_message(SIWE_NO_CAPS.parse().unwrap()).unwrap();
+        let public = "kepler:ens:example.eth://default/kv/public";
+        let prefix = |p: &str| {
+            [("prefix".to_string(), Value::from(p))]
+                .into_iter()
+                .collect::<std::collections::BTreeMap<_, _>>()
+        };
+
+        let mut kv = Capability::<Value>::default();
+        kv.with_actions_convert(public, [("kv/get", vec![prefix("a/")])])
+            .unwrap();
+        let mut credential = Capability::<Value>::default();
+        credential
+            .with_action_convert("urn:credential:type:type1", "credential/present", [])
+            .unwrap();
+        let mut overlapping = Capability::<Value>::default();
+        overlapping
+            .with_actions_convert(public, [("kv/get", vec![prefix("b/")]), ("kv/put", vec![])])
+            .unwrap();
+
+        let combined = combine_capabilities(&[
+            build(&kv),
+            build(&credential),
+            SIWE_NO_CAPS.parse().unwrap(),
+            build(&overlapping),
+        ])
+        .unwrap();
+        assert_eq!(combined.namespaces().count(), 2);
+        assert_eq!(combined.action_count(), 3);
+        assert_eq!(
+            combined.can(public, "kv/get").unwrap().unwrap().as_ref(),
+            [prefix("a/"), prefix("b/")]
+        );
+
+        let mut invalid = build(&kv);
+        invalid.statement = None;
+        let err = combine_capabilities(&[build(&kv), invalid]).unwrap_err();
+        assert_eq!(err.index, 1);
+    }
+
+    #[test]
+    fn combine_unrestricted() {
+        let build =
+            |cap: &Capability<Value>| cap.build_message(SIWE_NO_CAPS.parse().unwrap()).unwrap();
+        let kv = "kepler:ens:example.eth://default/kv";
+        let mut unrestricted = Capability::<Value>::default();
+        unrestricted.with_action_convert(kv, "kv/get", []).unwrap();
+        let mut limited = Capability::<Value>::default();
+        limited
+            .with_action_convert(
+                kv,
+                "kv/get",
+                [[("max".to_string(), Value::from(1))].into_iter().collect()],
+            )
+            .unwrap();
+
+        for messages in [
+            [build(&unrestricted), build(&limited)],
+            [build(&limited), build(&unrestricted)],
+        ] {
+            let combined = combine_capabilities(&messages).unwrap();
+            assert!(combined
+                .can(kv, "kv/get")
+                .unwrap()
+                .unwrap()
+                .as_ref()
+                .is_empty());
+        }
+    }
+
+    #[test]
+    fn error_codes() {
+        let mut msg: Message = SIWE.trim().parse().unwrap();
+        msg.statement = None;
+        assert_eq!(
+            Capability::<Value>::extract_and_verify(&msg)
+                .unwrap_err()
+                .code(),
+            "incorrect_statement"
+        );
+
+        msg.resources = vec!["urn:recap:!!".parse().unwrap()];
+        assert_eq!(
+            Capability::<Value>::extract_and_verify(&msg)
+                .unwrap_err()
+                .code(),
+            "invalid_base64"
+        );
+        assert_eq!(
+            Capability::<Value>::extract_and_verify_str("not a siwe message")
+                .unwrap_err()
+                .code(),
+            "invalid_message"
+        );
+        assert_eq!(
+            EncodingError::MissingExpiration.code(),
+            "missing_expiration"
+        );
+    }
+
+    #[test]
+    fn error_variants() {
+        // the error enums are non_exhaustive, so callers outside this crate need a wildcard arm
+        fn describe(error: &VerificationError) -> &'static str {
+            match error {
+                VerificationError::IncorrectStatement(_) => "statement",
+                VerificationError::Decoding(DecodingError::Resource { source, .. }) => {
+                    match **source {
+                        DecodingError::Base64Decode(_) => "base64",
+                        _ => "resource",
+                    }
+                }
+                VerificationError::Expired(_) | VerificationError::NotYetValid(_) => "time",
+                _ => "other",
+            }
+        }
+
+        let mut msg: Message = SIWE.trim().parse().unwrap();
+        msg.statement = None;
+        let error = Capability::<Value>::extract_and_verify(&msg).unwrap_err();
+        assert_eq!(describe(&error), "statement");
+
+        msg.resources = vec!["urn:recap:!!".parse().unwrap()];
+        let error = Capability::<Value>::extract_and_verify(&msg).unwrap_err();
+        assert_eq!(describe(&error), "base64");
+
+        let error = Capability::<Value>::extract_and_verify_str("not a siwe message").unwrap_err();
+        assert_eq!(describe(&error), "other");
+
+        // errors can be cloned, e.g. to hand them to several subscribers
+        let cloned = error.clone();
+        assert_eq!(cloned.to_string(), error.to_string());
+        assert_eq!(cloned.code(), "invalid_message");
+    }
+
+    #[test]
+    fn unknown_payload_fields() {
+        let msg: Message = SIWE.trim().parse().unwrap();
+        let resource = ResourceUrn::from_uri(capability_resource(&msg).unwrap()).unwrap();
+        let mut payload: Value = serde_json::from_slice(
+            &base64::decode_config(resource.payload(), base64::URL_SAFE_NO_PAD).unwrap(),
+        )
+        .unwrap();
+        payload["extra"] = true.into();
+        let mut extended = msg;
+        *extended.resources.last_mut().unwrap() = format!(
+            "urn:recap:{}",
+            base64::encode_config(payload.to_string(), base64::URL_SAFE_NO_PAD)
+        )
+        .parse()
+        .unwrap();
+
+        match Capability::<Value>::extract_and_verify(&extended) {
+            Err(VerificationError::Decoding(DecodingError::Resource { source, .. })) => {
+                assert!(matches!(*source, DecodingError::De(ref e)
+                    if e.to_string().contains("unknown field `extra`")));
+            }
+            other => panic!("unexpected result: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn separators_in_targets() {
+        let tricky = "kepler:ens:example.eth://default/kv/a,b;c'.(2)'kv':'delete'for'x";
+        let mut cap = Capability::<Value>::default();
+        cap.with_action_convert(tricky, "kv/get", []).unwrap();
+        let msg = cap.build_message(SIWE_NO_CAPS.parse().unwrap()).unwrap();
+
+        let reparsed: Message = msg.to_string().parse().unwrap();
+        assert_eq!(
+            Capability::<Value>::extract_and_verify(&reparsed)
+                .unwrap()
+                .unwrap(),
+            cap
+        );
+        assert_eq!(
+            reparsed.statement.as_ref().unwrap().matches(" (").count(),
+            1
+        );
+
+        // a clause separator cannot appear in a target
+        assert!(
+            "kepler:ens:example.eth://default/kv/a'. (2) 'kv': 'delete' for 'x"
+                .parse::<iri_string::types::UriString>()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn parse_generated_statement() {
+        for fixture in [SIWE, SIWE_WITH_STATEMENT] {
+            let msg: Message = fixture.trim().parse().unwrap();
+            assert_eq!(
+                parse_statement::<Value>(msg.statement.as_ref().unwrap()).unwrap(),
+                Capability::<Value>::extract_and_verify(&msg)
+                    .unwrap()
+                    .unwrap()
+            );
+        }
+
+        let generated = Capability::<Value>::extract_and_verify_str(SIWE.trim())
+            .unwrap()
+            .unwrap()
+            .to_statement();
+        assert_eq!(
+            parse_statement::<Value>(&format!("{generated} \n")).unwrap(),
+            parse_statement::<Value>(&generated).unwrap()
+        );
+        for invalid in [
+            "Sign in.",
+            &generated.replace("(2)", "(3)"),
+            &generated.replace(" for ", " on "),
+            &format!("{generated} trailing"),
+        ] {
+            assert!(matches!(
+                parse_statement::<Value>(invalid),
+                Err(DecodingError::InvalidStatement(_))
+            ));
+        }
+    }
+
     #[test]
     fn verify_interleaved_resources() {
         let msg: Message = SIWE_WITH_INTERLEAVED_RES.trim().parse().unwrap();
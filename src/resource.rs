@@ -0,0 +1,53 @@
+use crate::{Capability, DecodingError, RESOURCE_PREFIX};
+use iri_string::types::UriString;
+use serde::Deserialize;
+
+/// A single ReCap resource URI, which can be inspected without extracting capabilities from a message.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ResourceUrn {
+    uri: UriString,
+}
+
+impl ResourceUrn {
+    /// Check that a URI is a ReCap resource.
+    ///
+    /// The payload is not decoded until [`ResourceUrn::decode`] is called.
+    pub fn from_uri(uri: &UriString) -> Result<Self, DecodingError> {
+        if uri.as_str().starts_with(RESOURCE_PREFIX) {
+            Ok(Self { uri: uri.clone() })
+        } else {
+            Err(DecodingError::InvalidResourcePrefix(uri.to_string()))
+        }
+    }
+
+    /// The encoded payload, following the ReCap prefix.
+    pub fn payload(&self) -> &str {
+        &self.uri.as_str()[RESOURCE_PREFIX.len()..]
+    }
+
+    /// Decode the capabilities set encoded in this resource.
+    pub fn decode<NB>(&self) -> Result<Capability<NB>, DecodingError>
+    where
+        NB: for<'a> Deserialize<'a>,
+    {
+        Capability::decode(self.payload())
+    }
+
+    /// The full resource URI, including the ReCap prefix.
+    pub fn as_uri(&self) -> &UriString {
+        &self.uri
+    }
+
+    /// Unwrap the full resource URI.
+    pub fn into_inner(self) -> UriString {
+        self.uri
+    }
+}
+
+impl TryFrom<&UriString> for ResourceUrn {
+    type Error = DecodingError;
+
+    fn try_from(uri: &UriString) -> Result<Self, Self::Error> {
+        Self::from_uri(uri)
+    }
+}
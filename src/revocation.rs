@@ -0,0 +1,35 @@
+use crate::{Capability, VerificationError};
+use serde::Deserialize;
+use siwe::Message;
+use std::future::Future;
+
+/// A store of revoked delegations, keyed by the nonce of the SIWE message which granted them.
+///
+/// Lookups must be `Send`, so that verification can run on a multi-threaded executor.
+pub trait RevocationStore {
+    /// Check if the delegation with the given nonce has been revoked.
+    fn is_revoked(&self, nonce: &str) -> impl Future<Output = bool> + Send;
+}
+
+impl<NB> Capability<NB>
+where
+    NB: for<'a> Deserialize<'a>,
+{
+    /// Extract the encoded capabilities from a SIWE message and ensures the correctness of the statement,
+    /// then checks that the message has not been revoked.
+    ///
+    /// The statement is verified before the store is consulted, so invalid messages never reach it.
+    pub async fn extract_and_verify_with_store<S>(
+        message: &Message,
+        store: &S,
+    ) -> Result<Option<Self>, VerificationError>
+    where
+        S: RevocationStore,
+    {
+        let caps = Self::extract_and_verify(message)?;
+        if store.is_revoked(&message.nonce).await {
+            return Err(VerificationError::Revoked(message.nonce.clone()));
+        }
+        Ok(caps)
+    }
+}
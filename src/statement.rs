@@ -0,0 +1,251 @@
+use crate::{capability::wildcard_prefix, Capability, DecodingError};
+use iri_string::types::UriString;
+use std::collections::BTreeMap;
+use ucan_capabilities_object::{Ability, AbilityNameRef, AbilityNamespaceRef};
+
+pub(crate) const STATEMENT_PREAMBLE: &str =
+    "I further authorize the stated URI to perform the following actions on my behalf:";
+
+/// Produces the human-readable wording of a ReCap statement.
+///
+/// Every method defaults to the wording specified by EIP-5573. A message built with a custom renderer
+/// only verifies against the same renderer.
+pub trait StatementRenderer {
+    /// The sentence introducing the list of capabilities.
+    fn preamble(&self) -> String {
+        STATEMENT_PREAMBLE.to_string()
+    }
+
+    /// Render the abilities granted within one namespace for a target.
+    fn line(
+        &self,
+        target: &UriString,
+        namespace: &AbilityNamespaceRef<'_>,
+        names: &[AbilityNameRef<'_>],
+    ) -> String {
+        format!(
+            "'{}': {} for '{}'.",
+            namespace,
+            names
+                .iter()
+                .map(|an| format!("'{an}'"))
+                .collect::<Vec<String>>()
+                .join(", "),
+            target
+        )
+    }
+
+    /// Prefix a rendered line with its position, starting from 1.
+    fn numbered(&self, n: usize, line: &str) -> String {
+        format!(" ({n}) {line}")
+    }
+
+    /// Whether targets granted identical abilities within a namespace share a single line.
+    fn groups_targets(&self) -> bool {
+        false
+    }
+
+    /// Render the abilities granted within one namespace for several targets, when grouping targets.
+    fn grouped_line(
+        &self,
+        targets: &[&UriString],
+        namespace: &AbilityNamespaceRef<'_>,
+        names: &[AbilityNameRef<'_>],
+    ) -> String {
+        match targets {
+            [target] => self.line(target, namespace, names),
+            _ => format!(
+                "'{}': {} for {}.",
+                namespace,
+                names
+                    .iter()
+                    .map(|an| format!("'{an}'"))
+                    .collect::<Vec<String>>()
+                    .join(", "),
+                targets
+                    .iter()
+                    .map(|t| format!("'{t}'"))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+
+/// Renders statements with the wording specified by EIP-5573.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultRenderer;
+
+/// Read the capabilities described by a ReCap statement with the EIP-5573 wording, without decoding a resource.
+///
+/// Any custom statement before the last preamble is skipped and trailing whitespace is ignored, as when
+/// verifying a message. Statements carry no note-benes or proofs, so
+/// the result only agrees with the encoded capabilities when those are empty.
+pub fn parse_statement<NB>(statement: &str) -> Result<Capability<NB>, DecodingError> {
+    let invalid = |reason: &str| DecodingError::InvalidStatement(reason.to_string());
+    let statement = statement.trim_end();
+    let start = statement
+        .rfind(STATEMENT_PREAMBLE)
+        .ok_or_else(|| invalid("missing preamble"))?;
+    let mut rest = &statement[start + STATEMENT_PREAMBLE.len()..];
+    let mut cap = Capability::new();
+    let mut n = 1;
+    while !rest.is_empty() {
+        let clause = format!("clause {n}");
+        rest = rest
+            .strip_prefix(&format!(" ({n}) '"))
+            .ok_or_else(|| invalid(&clause))?;
+        let (namespace, after) = rest.split_once("': ").ok_or_else(|| invalid(&clause))?;
+        let (names, after) = after.split_once(" for '").ok_or_else(|| invalid(&clause))?;
+        // targets cannot contain spaces, so the clause ends at the next space
+        let end = after.find(' ').unwrap_or(after.len());
+        let target = after[..end]
+            .strip_suffix("'.")
+            .ok_or_else(|| invalid(&clause))?;
+        let target: UriString = target.parse().map_err(|_| invalid(&clause))?;
+        let abilities = names
+            .split(", ")
+            .map(|name| {
+                name.strip_prefix('\'')
+                    .and_then(|name| name.strip_suffix('\''))
+                    .and_then(|name| format!("{namespace}/{name}").parse::<Ability>().ok())
+                    .map(|ability| (ability, []))
+                    .ok_or_else(|| invalid(&clause))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        cap.with_actions(target, abilities);
+        rest = &after[end..];
+        n += 1;
+    }
+    Ok(cap)
+}
+
+impl StatementRenderer for DefaultRenderer {}
+
+/// Renders statements with the EIP-5573 wording, but lists targets granted identical abilities within a
+/// namespace on a single line.
+///
+/// The encoded resource still lists every target separately.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GroupedRenderer;
+
+impl StatementRenderer for GroupedRenderer {
+    fn groups_targets(&self) -> bool {
+        true
+    }
+}
+
+/// Renders statements with the EIP-5573 wording, but describes wildcard targets ending in `/*` as all
+/// resources under their prefix.
+///
+/// Other implementations generate the EIP-5573 wording, so messages built with this renderer only verify
+/// against it.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WildcardRenderer;
+
+impl StatementRenderer for WildcardRenderer {
+    fn line(
+        &self,
+        target: &UriString,
+        namespace: &AbilityNamespaceRef<'_>,
+        names: &[AbilityNameRef<'_>],
+    ) -> String {
+        match wildcard_prefix(target.as_str()) {
+            Some(prefix) => format!(
+                "'{}': {} for all resources under '{}'.",
+                namespace,
+                names
+                    .iter()
+                    .map(|an| format!("'{an}'"))
+                    .collect::<Vec<String>>()
+                    .join(", "),
+                prefix
+            ),
+            None => DefaultRenderer.line(target, namespace, names),
+        }
+    }
+}
+
+/// Renders statements with human-readable labels in place of ability names, otherwise using the default wording.
+///
+/// Only the statement uses the labels; the encoded resource keeps the canonical abilities. As the
+/// statement is what users read, labels must describe their abilities faithfully.
+#[derive(Clone, Debug, Default)]
+pub struct LabelledRenderer {
+    labels: BTreeMap<Ability, String>,
+}
+
+impl LabelledRenderer {
+    /// Create a renderer without any labels.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Render the given ability with a label.
+    pub fn with_label(mut self, ability: Ability, label: impl Into<String>) -> Self {
+        self.labels.insert(ability, label.into());
+        self
+    }
+}
+
+impl StatementRenderer for LabelledRenderer {
+    fn line(
+        &self,
+        target: &UriString,
+        namespace: &AbilityNamespaceRef<'_>,
+        names: &[AbilityNameRef<'_>],
+    ) -> String {
+        format!(
+            "'{}': {} for '{}'.",
+            namespace,
+            names
+                .iter()
+                .map(
+                    |an| match self.labels.get(format!("{namespace}/{an}").as_str()) {
+                        Some(label) => format!("'{label}'"),
+                        None => format!("'{an}'"),
+                    }
+                )
+                .collect::<Vec<String>>()
+                .join(", "),
+            target
+        )
+    }
+}
+
+/// Renders statements with the EIP-5573 wording, but lists prioritised abilities first within each line.
+///
+/// Other abilities follow in their canonical order. Only the statement is reordered; the encoded resource
+/// keeps the canonical order.
+#[derive(Clone, Debug, Default)]
+pub struct PriorityRenderer {
+    priority: Vec<Ability>,
+}
+
+impl PriorityRenderer {
+    /// Create a renderer listing the given abilities first, in the given order.
+    pub fn new(priority: impl IntoIterator<Item = Ability>) -> Self {
+        Self {
+            priority: priority.into_iter().collect(),
+        }
+    }
+}
+
+impl StatementRenderer for PriorityRenderer {
+    fn line(
+        &self,
+        target: &UriString,
+        namespace: &AbilityNamespaceRef<'_>,
+        names: &[AbilityNameRef<'_>],
+    ) -> String {
+        let mut names = names.to_vec();
+        names.sort_by_key(|an| {
+            let ability = format!("{namespace}/{an}");
+            self.priority
+                .iter()
+                .position(|p| p.as_ref() == ability)
+                .unwrap_or(usize::MAX)
+        });
+        DefaultRenderer.line(target, namespace, &names)
+    }
+}
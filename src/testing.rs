@@ -0,0 +1,169 @@
+//! Helpers for checking other ReCap implementations against this crate.
+use crate::Capability;
+use iri_string::types::UriString;
+use proptest::prelude::*;
+use serde::{Deserialize, Serialize};
+use siwe::Message;
+use std::fmt::Debug;
+use ucan_capabilities_object::Ability;
+
+pub(crate) fn base_message() -> Message {
+    Message {
+        domain: "example.com".parse().unwrap(),
+        address: Default::default(),
+        statement: None,
+        uri: "did:key:example".parse().unwrap(),
+        version: siwe::Version::V1,
+        chain_id: 1,
+        nonce: "mynonce1".into(),
+        issued_at: "2022-06-21T12:00:00.000Z".parse().unwrap(),
+        expiration_time: None,
+        not_before: None,
+        request_id: None,
+        resources: vec![],
+    }
+}
+
+/// Build a SIWE message from a capabilities set, then extract and verify it, asserting the result is equal.
+pub fn assert_roundtrip<NB>(cap: &Capability<NB>)
+where
+    NB: Serialize + for<'a> Deserialize<'a> + PartialEq + Debug,
+{
+    let message = cap
+        .build_message(base_message())
+        .expect("failed to build SIWE message");
+    let extracted = Capability::<NB>::extract_and_verify(&message)
+        .expect("failed to extract and verify capabilities")
+        .unwrap_or_default();
+    assert_eq!(&extracted, cap);
+}
+
+/// Generate valid capabilities sets, without note-benes or proofs.
+pub fn capability() -> impl Strategy<Value = Capability<serde_json::Value>> {
+    let target = "(https://example\\.com/|kepler:ens:example\\.eth://default/)[a-z0-9-]{0,8}\\*?"
+        .prop_map(|t| t.parse::<UriString>().unwrap());
+    let ability =
+        "[a-z][a-z0-9-]{0,6}/[a-z*][a-z0-9-]{0,6}".prop_map(|a| a.parse::<Ability>().unwrap());
+    prop::collection::btree_map(target, prop::collection::btree_set(ability, 1..4), 0..4).prop_map(
+        |grants| {
+            let mut cap = Capability::new();
+            for (target, abilities) in grants {
+                cap.with_actions(target, abilities.into_iter().map(|a| (a, [])));
+            }
+            cap
+        },
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A change to an encoded capability object, to exercise the decoder beyond well-formed payloads.
+    #[derive(Debug, Clone)]
+    enum Mutation {
+        None,
+        Extra(String, serde_json::Value),
+        Remove(&'static str),
+        Replace(&'static str, serde_json::Value),
+        ReplaceAbilities(serde_json::Value),
+    }
+
+    impl Mutation {
+        fn apply(self, payload: &mut serde_json::Value) {
+            let object = payload.as_object_mut().unwrap();
+            match self {
+                Self::None => {}
+                Self::Extra(key, value) => {
+                    object.insert(key, value);
+                }
+                Self::Remove(key) => {
+                    object.remove(key);
+                }
+                Self::Replace(key, value) => {
+                    object.insert(key.into(), value);
+                }
+                Self::ReplaceAbilities(value) => {
+                    if let Some(abilities) =
+                        object["att"].as_object_mut().unwrap().values_mut().next()
+                    {
+                        *abilities = value;
+                    }
+                }
+            }
+        }
+    }
+
+    fn json() -> impl Strategy<Value = serde_json::Value> {
+        let leaf = prop_oneof![
+            Just(serde_json::Value::Null),
+            any::<bool>().prop_map(Into::into),
+            any::<i64>().prop_map(Into::into),
+            "[a-z/*:.]{0,12}".prop_map(Into::into),
+        ];
+        leaf.prop_recursive(3, 16, 4, |inner| {
+            prop_oneof![
+                prop::collection::vec(inner.clone(), 0..4).prop_map(Into::into),
+                prop::collection::btree_map("[a-z/*:]{0,8}", inner, 0..4)
+                    .prop_map(|m| m.into_iter().collect::<serde_json::Map<_, _>>().into()),
+            ]
+        })
+    }
+
+    fn mutation() -> impl Strategy<Value = Mutation> {
+        prop_oneof![
+            Just(Mutation::None),
+            ("[a-z]{1,4}", json())
+                .prop_filter("known field", |(key, _)| key != "att" && key != "prf")
+                .prop_map(|(key, value)| Mutation::Extra(key, value)),
+            prop_oneof![Just("att"), Just("prf")].prop_map(Mutation::Remove),
+            (prop_oneof![Just("att"), Just("prf")], json())
+                .prop_map(|(key, value)| Mutation::Replace(key, value)),
+            json().prop_map(Mutation::ReplaceAbilities),
+        ]
+    }
+
+    fn extract_payload(encoded: &str) -> Result<(), crate::VerificationError> {
+        let mut message = base_message();
+        message
+            .resources
+            .push(format!("urn:recap:{encoded}").parse().unwrap());
+        Capability::<serde_json::Value>::extract_and_verify(&message).map(|_| ())
+    }
+
+    proptest! {
+        #[test]
+        fn roundtrip(cap in capability()) {
+            assert_roundtrip(&cap);
+        }
+
+        #[test]
+        fn arbitrary_base64(encoded in "[A-Za-z0-9_-]{0,128}") {
+            prop_assert!(extract_payload(&encoded).is_err());
+        }
+
+        #[test]
+        fn arbitrary_payload(payload in prop::collection::vec(any::<u8>(), 0..128)) {
+            let encoded = base64::encode_config(payload, base64::URL_SAFE_NO_PAD);
+            prop_assert!(extract_payload(&encoded).is_err());
+        }
+
+        #[test]
+        fn mutated_payload((cap, mutation) in (capability(), mutation())) {
+            let mut payload = serde_json::to_value(&cap).unwrap();
+            let unchanged = matches!(mutation, Mutation::None);
+            let extra = matches!(mutation, Mutation::Extra(..));
+            mutation.apply(&mut payload);
+            let encoded = base64::encode_config(payload.to_string(), base64::URL_SAFE_NO_PAD);
+
+            let decoded = Capability::<serde_json::Value>::decode(&encoded);
+            if unchanged {
+                prop_assert_eq!(decoded.unwrap(), cap);
+            } else if extra {
+                prop_assert!(decoded.is_err());
+            }
+            // without a statement, extraction fails even when the payload decodes
+            prop_assert!(extract_payload(&encoded).is_err());
+        }
+    }
+}
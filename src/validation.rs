@@ -0,0 +1,92 @@
+use crate::Capability;
+use iri_string::types::UriString;
+use std::collections::BTreeMap;
+use ucan_capabilities_object::{Ability, AbilityNamespace, NotaBeneCollection};
+
+/// A target which is not valid for the namespace of an ability granted on it.
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+#[error("invalid target {target} for ability namespace {namespace}")]
+pub struct InvalidTarget {
+    /// The rejected target.
+    pub target: UriString,
+    /// The namespace whose validator rejected the target.
+    pub namespace: AbilityNamespace,
+}
+
+type Validator = Box<dyn Fn(&UriString) -> bool + Send + Sync>;
+
+/// Checks targets against a grammar registered for each ability namespace.
+///
+/// Targets of abilities in namespaces without a registered validator are always valid.
+#[derive(Default)]
+pub struct TargetValidator {
+    validators: BTreeMap<AbilityNamespace, Validator>,
+}
+
+impl TargetValidator {
+    /// Create a validator which accepts every target.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the grammar of targets for abilities in the given namespace.
+    pub fn with_target_validator(
+        mut self,
+        namespace: AbilityNamespace,
+        validator: impl Fn(&UriString) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.validators.insert(namespace, Box::new(validator));
+        self
+    }
+
+    /// Check that a target is valid for an ability.
+    pub fn check(&self, target: &UriString, ability: &Ability) -> Result<(), InvalidTarget> {
+        let namespace = ability.namespace();
+        match self
+            .validators
+            .iter()
+            .find(|(ns, _)| ns.as_ref() == namespace.as_ref())
+        {
+            Some((ns, valid)) if !valid(target) => Err(InvalidTarget {
+                target: target.clone(),
+                namespace: ns.clone(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check that every target in a capabilities set is valid for the abilities granted on it.
+    pub fn check_all<NB>(&self, cap: &Capability<NB>) -> Result<(), InvalidTarget> {
+        cap.abilities()
+            .iter()
+            .flat_map(|(target, abilities)| abilities.keys().map(move |ability| (target, ability)))
+            .try_for_each(|(target, ability)| self.check(target, ability))
+    }
+}
+
+impl std::fmt::Debug for TargetValidator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TargetValidator")
+            .field("namespaces", &self.validators.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl<NB> Capability<NB> {
+    /// Add a set of allowed action for the given target, with associated note-benes, if the target is
+    /// valid for every action.
+    ///
+    /// Nothing is added if any action is rejected.
+    pub fn with_actions_validated(
+        &mut self,
+        target: UriString,
+        abilities: impl IntoIterator<Item = (Ability, NotaBeneCollection<NB>)>,
+        validator: &TargetValidator,
+    ) -> Result<&mut Self, InvalidTarget> {
+        let abilities: Vec<_> = abilities.into_iter().collect();
+        for (ability, _) in &abilities {
+            validator.check(&target, ability)?;
+        }
+        Ok(self.with_actions(target, abilities))
+    }
+}